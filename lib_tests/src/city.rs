@@ -5,3 +5,9 @@ use protobuf_gen::ProtobufGen;
 pub struct City {
     pub name: String,
 }
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq, Eq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct District {
+    pub blocks: Vec<Vec<u32>>,
+}
@@ -3,7 +3,12 @@ extern crate proptest_derive;
 
 pub mod city;
 pub mod person;
+pub mod tree;
 
 pub mod proxy {
     include!("../proxy/yellow_book.rs");
+
+    pub mod tree {
+        include!("../proxy/tree.rs");
+    }
 }
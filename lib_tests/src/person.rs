@@ -1,6 +1,10 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, LinkedList, VecDeque};
 use std::convert::TryInto;
+use std::num::{NonZeroI64, NonZeroU32};
+use std::time::{Duration, SystemTime};
 
+use bytes::Bytes;
 use protobuf_gen::ProtobufGen;
 
 use crate::city::City;
@@ -41,23 +45,10 @@ pub enum Job {
 pub enum AreaCode {
     #[default]
     Seoul,
-    Seongnam,
+    Seongnam = 31,
     Jinhae,
-}
-
-#[derive(Debug, Default, Clone, Arbitrary, PartialEq, Eq, Hash)]
-pub struct NumberBuffer(Vec<u8>);
-
-impl Into<Vec<u8>> for NumberBuffer {
-    fn into(self) -> Vec<u8> {
-        self.0
-    }
-}
-
-impl From<Vec<u8>> for NumberBuffer {
-    fn from(vs: Vec<u8>) -> Self {
-        NumberBuffer(vs)
-    }
+    #[protobuf_gen(value = 51)]
+    Changwon,
 }
 
 #[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq, Eq, Hash)]
@@ -67,7 +58,7 @@ pub struct Car {
 }
 
 #[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq, Eq, Hash)]
-#[protobuf_gen(proxy_mod = "crate::proxy")]
+#[protobuf_gen(proxy_mod = "crate::proxy", prefix_values)]
 pub enum CarTag {
     #[default]
     None,
@@ -80,8 +71,7 @@ pub struct Person {
     #[protobuf_gen(skip)]
     pub _inner: i32,
     pub id: u8,
-    #[protobuf_gen(substitute = "bytes")]
-    pub number: NumberBuffer,
+    pub number: Vec<u8>,
     pub hobbies: Vec<u32>,
     pub job: Job,
     pub city: Option<City>,
@@ -115,11 +105,149 @@ pub struct OptionOfPerson {
     pub opaque_option: Option<Person>,
 }
 
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Meters(f64);
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Commute(pub Meters, #[protobuf_gen(name = "by")] pub Car);
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub enum Command {
+    #[default]
+    Stop,
+    Resize(u32, u32),
+    Drive(Car),
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Ping;
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Marker {
+    secret: u32,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub enum Signal {
+    #[default]
+    Idle,
+    Ping(Ping),
+    Marked(Marker),
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(
+    proxy_mod = "crate::proxy",
+    instantiate(PageOfPerson = "Page<Person>", PageOfCar = "Page<Car>")
+)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Directory {
+    pub people: Page<Person>,
+    pub cars: Page<Car>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Schedule {
+    pub interval: Duration,
+    pub laps: Vec<Duration>,
+    pub started: Option<SystemTime>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Attachment {
+    pub data: Vec<u8>,
+    pub digest: [u8; 4],
+    pub payload: Bytes,
+    pub thumbnail: Option<Vec<u8>>,
+    pub chunks: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Scalars {
+    pub flag: bool,
+    pub initial: char,
+    pub big: u128,
+    pub delta: i128,
+    pub id: Option<NonZeroU32>,
+    pub ids: Vec<NonZeroI64>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Memo<'a> {
+    pub title: Cow<'a, str>,
+    pub body: Cow<'a, [u8]>,
+    pub tags: Vec<Cow<'a, str>>,
+    pub pinned: bool,
+}
+
+/// Holds references, so it can be encoded but not decoded.
+#[derive(Debug, Clone, ProtobufGen)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Draft<'a> {
+    pub title: &'a str,
+    pub body: &'a [u8],
+    pub note: Option<&'a str>,
+    pub priority: u32,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Matrix {
+    pub rows: Vec<Vec<f64>>,
+    pub diagonal: Option<Vec<f64>>,
+    pub sparse: Vec<Option<f64>>,
+    pub blocks: Vec<[Vec<f64>; 2]>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Graph {
+    pub adjacency: HashMap<u32, Vec<u32>>,
+    pub weights: BTreeMap<u32, BTreeMap<u32, f64>>,
+    pub owners: Vec<HashSet<City>>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct Checksum {
+    #[protobuf_gen(encoding = "fixed64")]
+    pub hash: u64,
+    #[protobuf_gen(encoding = "sfixed32")]
+    pub offset: i32,
+    #[protobuf_gen(encoding = "fixed32")]
+    pub sizes: Vec<u16>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy")]
+pub struct CollectionsOfCar {
+    pub tags: BTreeSet<String>,
+    pub queue: VecDeque<Car>,
+    pub list: LinkedList<Car>,
+    pub pair: [Car; 2],
+}
+
 #[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
 #[protobuf_gen(proxy_mod = "crate::proxy")]
 pub struct MapOfPerson {
-    #[protobuf_gen(substitute = "map<string, Person>")]
     pub map: HashMap<String, Person>,
+    pub ordered_map: BTreeMap<u32, Person>,
 }
 
 #[derive(Debug, Default, Clone, ProtobufGen, Arbitrary, PartialEq)]
@@ -137,7 +265,6 @@ pub enum VariousPerson {
         inner: HashSet<Person>,
     },
     MapOfPerson {
-        #[protobuf_gen(substitute = "map<string, Person>")]
         inner: HashMap<String, Person>,
     },
     OptionOfPerson {
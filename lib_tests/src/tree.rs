@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::rc::Rc;
+use std::sync::Arc;
+
 use protobuf_gen::ProtobufGen;
 
 #[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
@@ -11,6 +16,21 @@ pub enum Node {
     Tree2 {
         nodes: Option<Node2>,
     },
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+    },
+    Leaf(Box<Node2>),
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub enum Value {
+    #[default]
+    Empty,
+    List(Vec<u32>),
+    Maybe(Option<u32>),
+    Table(HashMap<u32, u32>),
 }
 
 #[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
@@ -23,4 +43,58 @@ pub struct Node2 {
 #[protobuf_gen(proxy_mod = "crate::proxy::tree")]
 pub struct Tree {
     pub nodes: Vec<Node>,
+    pub root: Option<Box<Node>>,
+    pub shared: Arc<Node2>,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub struct List {
+    pub value: u32,
+    pub next: Option<Rc<List>>,
+}
+
+/// Named like `bytes::Bytes`, which `Trip` does not refer to.
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub struct Bytes {
+    pub size: u32,
+}
+
+/// Named like `std::time::Duration`, which `Trip` does not refer to either.
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub struct Duration {
+    pub minutes: u32,
+}
+
+/// An enum named like `std::time::SystemTime`, which `Trip` does not refer to either.
+#[derive(Debug, Default, Clone, Copy, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub enum SystemTime {
+    #[default]
+    Morning,
+    Evening,
+}
+
+/// Reordered since its numbers were locked, with a tag given to a new field.
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub struct Shelf {
+    #[protobuf_gen(tag = 4)]
+    pub d: u32,
+    pub c: u32,
+    pub a: u32,
+    pub b: u32,
+}
+
+#[derive(Debug, Default, Clone, ProtobufGen, PartialEq)]
+#[protobuf_gen(proxy_mod = "crate::proxy::tree")]
+pub struct Trip {
+    pub length: Duration,
+    pub stops: Vec<Duration>,
+    pub luggage: Bytes,
+    pub departure: SystemTime,
+    pub arrivals: Vec<SystemTime>,
+    pub return_at: Option<SystemTime>,
 }
@@ -1,7 +1,13 @@
-use lib_tests::city::City;
+use lib_tests::city::{City, District};
 use lib_tests::person::*;
+use lib_tests::tree::{self, List, Node, Node2, Shelf, Tree, Trip, Value};
 use proptest::prelude::*;
+use std::borrow::Cow;
 use std::io::Cursor;
+use std::num::{NonZeroI64, NonZeroU32};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use protobuf_gen::ProtobufGen;
 
@@ -10,7 +16,7 @@ fn test_encode_decode_person() {
     let person = Person {
         _inner: 0,
         id: 23,
-        number: vec![1, 2, 3].into(),
+        number: vec![1, 2, 3],
         hobbies: vec![7, 2],
         job: Job::Programmer {
             skill: "Rust".to_string(),
@@ -46,6 +52,415 @@ fn test_encode_decode_vec_of_opaque() {
     assert_eq!(vec_of_car, decoded);
 }
 
+#[test]
+fn test_enum_discriminants() {
+    assert_eq!(i32::try_from(AreaCode::Seongnam).unwrap(), 31);
+    assert_eq!(i32::try_from(AreaCode::Jinhae).unwrap(), 32);
+    assert_eq!(AreaCode::try_from(51).unwrap(), AreaCode::Changwon);
+}
+
+#[test]
+fn test_encode_decode_map_of_person() {
+    let person = Person {
+        id: 7,
+        ..Person::default()
+    };
+    let map_of_person = MapOfPerson {
+        map: [("seven".to_string(), person.clone())].into(),
+        ordered_map: [(7, person)].into(),
+    };
+
+    let mut buffer = Vec::new();
+    map_of_person.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = MapOfPerson::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(map_of_person, decoded);
+}
+
+#[test]
+fn test_encode_decode_collections_of_car() {
+    let collections = CollectionsOfCar {
+        tags: ["b".to_string(), "a".to_string()].into(),
+        queue: [Car { number: 1 }, Car { number: 2 }].into(),
+        list: [Car { number: 3 }].into(),
+        pair: [Car { number: 4 }, Car { number: 5 }],
+    };
+
+    let mut buffer = Vec::new();
+    collections.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = CollectionsOfCar::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(collections, decoded);
+}
+
+#[test]
+fn test_encode_decode_tuple_struct() {
+    let commute = Commute(Meters::default(), Car { number: 5 });
+
+    let mut buffer = Vec::new();
+    commute.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Commute::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(commute, decoded);
+}
+
+#[test]
+fn test_encode_decode_tuple_variants() {
+    for command in [
+        Command::Stop,
+        Command::Resize(3, 4),
+        Command::Drive(Car { number: 7 }),
+    ] {
+        let mut buffer = Vec::new();
+        command.clone().to_protobuf(&mut buffer).unwrap();
+        let decoded = Command::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(command, decoded);
+    }
+}
+
+#[test]
+fn test_encode_decode_collection_variants() {
+    for value in [
+        Value::Empty,
+        Value::List(vec![1, 2, 3]),
+        Value::List(vec![]),
+        Value::Maybe(Some(0)),
+        Value::Maybe(None),
+        Value::Table([(1, 2), (3, 4)].into_iter().collect()),
+    ] {
+        let mut buffer = Vec::new();
+        value.clone().to_protobuf(&mut buffer).unwrap();
+        let decoded = Value::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(value, decoded);
+    }
+}
+
+#[test]
+fn test_encode_decode_empty_messages() {
+    for signal in [
+        Signal::Idle,
+        Signal::Ping(Ping),
+        Signal::Marked(Marker::default()),
+    ] {
+        let mut buffer = Vec::new();
+        signal.clone().to_protobuf(&mut buffer).unwrap();
+        let decoded = Signal::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(signal, decoded);
+    }
+
+    let buffer = Vec::<u8>::try_from(Ping).unwrap();
+    assert!(buffer.is_empty());
+    assert_eq!(Ping::from_protobuf(&buffer[..]).unwrap(), Ping);
+}
+
+#[test]
+fn test_encode_decode_generic() {
+    let directory = Directory {
+        people: Page {
+            items: vec![Person {
+                id: 3,
+                ..Person::default()
+            }],
+            next: Some(2),
+        },
+        cars: Page {
+            items: vec![Car { number: 1 }],
+            next: None,
+        },
+    };
+
+    let mut buffer = Vec::new();
+    directory.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Directory::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(directory, decoded);
+}
+
+#[test]
+fn test_encode_decode_well_known_types() {
+    let schedule = Schedule {
+        interval: Duration::from_millis(1500),
+        laps: vec![Duration::from_secs(3), Duration::from_nanos(7)],
+        started: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+    };
+
+    let mut buffer = Vec::new();
+    schedule.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Schedule::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(schedule, decoded);
+}
+
+#[test]
+fn test_encode_decode_integer_encodings() {
+    let checksum = Checksum {
+        hash: 0xdead_beef_dead_beef,
+        offset: -3,
+        sizes: vec![7, 65535],
+    };
+
+    let mut buffer = Vec::new();
+    checksum.clone().to_protobuf(&mut buffer).unwrap();
+    // a tag, then 8 bytes for the fixed64 hash.
+    assert_eq!(&buffer[1..9], &0xdead_beef_dead_beef_u64.to_le_bytes());
+    let decoded = Checksum::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(checksum, decoded);
+}
+
+#[test]
+fn test_encode_decode_bytes() {
+    let attachment = Attachment {
+        data: vec![0, 1, 255],
+        digest: [0xde, 0xad, 0xbe, 0xef],
+        payload: bytes::Bytes::from_static(b"payload"),
+        thumbnail: Some(vec![42]),
+        chunks: vec![vec![1, 2], vec![]],
+    };
+
+    let mut buffer = Vec::new();
+    attachment.clone().to_protobuf(&mut buffer).unwrap();
+    // a tag, a length, then the bytes themselves.
+    assert_eq!(&buffer[..5], &[0x0a, 3, 0, 1, 255]);
+    let decoded = Attachment::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(attachment, decoded);
+}
+
+#[test]
+fn test_decode_bytes_of_wrong_length() {
+    let proxy = lib_tests::proxy::Attachment {
+        digest: vec![1, 2, 3],
+        ..Default::default()
+    };
+
+    let buffer = prost::Message::encode_to_vec(&proxy);
+    let error = Attachment::from_protobuf(&mut Cursor::new(buffer)).unwrap_err();
+    assert!(matches!(
+        cause(&error),
+        protobuf_gen::Error::InvalidLength {
+            field,
+            expected: 4,
+            actual: 3,
+        } if field == "digest"
+    ));
+}
+
+/// Returns the error a conversion failed with, under those naming what was being converted.
+fn cause(mut error: &protobuf_gen::Error) -> &protobuf_gen::Error {
+    while let protobuf_gen::Error::TryFromError(_, source) = error {
+        match source.downcast_ref() {
+            Some(source) => error = source,
+            None => break,
+        }
+    }
+    error
+}
+
+#[test]
+fn test_decode_shared_bytes() {
+    let attachment = Attachment {
+        payload: bytes::Bytes::from_static(b"payload"),
+        ..Default::default()
+    };
+
+    let mut buffer = Vec::new();
+    attachment.clone().to_protobuf(&mut buffer).unwrap();
+    let buffer = bytes::Bytes::from(buffer);
+    let decoded = Attachment::from_protobuf(buffer.clone()).unwrap();
+    assert_eq!(attachment, decoded);
+    // the payload points into the input rather than a copy of it.
+    assert!(buffer.as_ptr_range().contains(&decoded.payload.as_ptr()));
+}
+
+#[test]
+fn test_encode_decode_borrowed() {
+    let title = String::from("groceries");
+    let memo = Memo {
+        title: Cow::Borrowed(&title),
+        body: Cow::Borrowed(b"milk"),
+        tags: vec![Cow::Borrowed("home"), Cow::Owned("weekly".to_owned())],
+        pinned: true,
+    };
+
+    let mut buffer = Vec::new();
+    memo.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Memo::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(memo, decoded);
+    assert!(matches!(decoded.title, Cow::Owned(_)));
+
+    let mut buffer = Vec::new();
+    memo.clone()
+        .to_protobuf_length_delimited(&mut buffer)
+        .unwrap();
+    let decoded = Memo::from_protobuf_length_delimited(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(memo, decoded);
+
+    // borrowed fields are written after the proxy, and left out when empty like in the proxy.
+    let empty = Memo {
+        pinned: true,
+        ..Memo::default()
+    };
+    let proxy: lib_tests::proxy::Memo = empty.clone().try_into().unwrap();
+    assert_eq!(
+        Vec::<u8>::try_from(empty).unwrap(),
+        prost::Message::encode_to_vec(&proxy)
+    );
+
+    let required = Vec::<u8>::try_from(memo.clone()).unwrap().len();
+    let mut buffer = [0; 8];
+    assert!(matches!(
+        memo.to_protobuf(&mut &mut buffer[..]),
+        Err(protobuf_gen::Error::InsufficientBuffer { required: r, remaining: 8 }) if r == required
+    ));
+
+    let draft = Draft {
+        title: &title,
+        body: b"milk",
+        note: Some(""),
+        priority: 2,
+    };
+    let buffer = Vec::<u8>::try_from(draft).unwrap();
+    let decoded: lib_tests::proxy::Draft = prost::Message::decode(&buffer[..]).unwrap();
+    assert_eq!(decoded.title, "groceries");
+    assert_eq!(decoded.body, b"milk");
+    assert_eq!(decoded.note.as_deref(), Some(""));
+    assert_eq!(decoded.priority, 2);
+}
+
+#[test]
+fn test_encode_decode_scalars() {
+    let scalars = Scalars {
+        flag: true,
+        initial: '한',
+        big: u128::MAX - 1,
+        delta: i128::MIN,
+        id: NonZeroU32::new(7),
+        ids: vec![NonZeroI64::new(-1).unwrap()],
+    };
+
+    let mut buffer = Vec::new();
+    scalars.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Scalars::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(scalars, decoded);
+}
+
+#[test]
+fn test_decode_invalid_scalars() {
+    use lib_tests::proxy::Scalars as Proxy;
+    use protobuf_gen::Error;
+
+    let valid = Proxy {
+        big: vec![0; 16],
+        delta: vec![0; 16],
+        ..Default::default()
+    };
+    let decode = |proxy: Proxy| {
+        let buffer = prost::Message::encode_to_vec(&proxy);
+        Scalars::from_protobuf(&mut Cursor::new(buffer)).unwrap_err()
+    };
+
+    let error = decode(Proxy {
+        initial: 0xd800,
+        ..valid.clone()
+    });
+    assert!(matches!(
+        cause(&error),
+        Error::InvalidChar { field, value: 0xd800 } if field == "initial"
+    ));
+
+    let error = decode(Proxy {
+        id: Some(0),
+        ..valid.clone()
+    });
+    assert!(matches!(cause(&error), Error::ZeroValue { field } if field == "id"));
+
+    let error = decode(Proxy {
+        ids: vec![0],
+        ..valid.clone()
+    });
+    assert!(matches!(cause(&error), Error::ZeroValue { field } if field == "ids"));
+
+    let error = decode(Proxy {
+        big: vec![0; 15],
+        ..valid
+    });
+    assert!(matches!(
+        cause(&error),
+        Error::InvalidLength { field, expected: 16, actual: 15 } if field == "big"
+    ));
+}
+
+#[test]
+fn test_encode_decode_nested_collections() {
+    let matrix = Matrix {
+        rows: vec![vec![1.0, 0.0], vec![], vec![0.5]],
+        diagonal: Some(vec![]),
+        sparse: vec![None, Some(2.0), None],
+        blocks: vec![[vec![1.0], vec![2.0, 3.0]]],
+    };
+
+    let mut buffer = Vec::new();
+    matrix.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Matrix::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(matrix, decoded);
+
+    let graph = Graph {
+        adjacency: vec![(1, vec![2, 3]), (2, vec![]), (3, vec![1])]
+            .into_iter()
+            .collect(),
+        weights: vec![(1, vec![(2, 0.5)].into_iter().collect())]
+            .into_iter()
+            .collect(),
+        owners: vec![
+            vec![City::default()].into_iter().collect(),
+            Default::default(),
+        ],
+    };
+
+    let mut buffer = Vec::new();
+    graph.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Graph::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(graph, decoded);
+
+    let district = District {
+        blocks: vec![vec![1, 2], vec![3]],
+    };
+
+    let mut buffer = Vec::new();
+    district.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = District::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(district, decoded);
+}
+
+#[test]
+fn test_encode_decode_recursive_types() {
+    let leaf = |x| Box::new(Node::Leaf(Box::new(Node2 { x: Some(x) })));
+    let tree = Tree {
+        nodes: vec![Node::None],
+        root: Some(Box::new(Node::Branch {
+            left: leaf(1),
+            right: Box::new(Node::Branch {
+                left: leaf(2),
+                right: Box::new(Node::None),
+            }),
+        })),
+        shared: Arc::new(Node2 { x: Some(3) }),
+    };
+
+    let mut buffer = Vec::new();
+    tree.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Tree::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(tree, decoded);
+
+    let tail = Rc::new(List {
+        value: 2,
+        next: None,
+    });
+    let list = List {
+        value: 1,
+        next: Some(tail.clone()),
+    };
+
+    let mut buffer = Vec::new();
+    list.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = List::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(list, decoded);
+}
+
 fn do_test_encode_decode_person(mut person: Person) -> eyre::Result<()> {
     let mut buffer = Vec::new();
     person.clone().to_protobuf(&mut buffer)?;
@@ -64,3 +479,37 @@ proptest::proptest! {
         prop_assert!(do_test_encode_decode_person(person).is_ok());
     }
 }
+
+#[test]
+fn test_encode_decode_well_known_names() {
+    let trip = Trip {
+        length: tree::Duration { minutes: 90 },
+        stops: vec![tree::Duration { minutes: 5 }, tree::Duration::default()],
+        luggage: tree::Bytes { size: 23 },
+        departure: tree::SystemTime::Evening,
+        arrivals: vec![tree::SystemTime::Morning, tree::SystemTime::Evening],
+        return_at: Some(tree::SystemTime::Evening),
+    };
+
+    let mut buffer = Vec::new();
+    trip.clone().to_protobuf(&mut buffer).unwrap();
+    let decoded = Trip::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(trip, decoded);
+}
+
+#[test]
+fn test_encode_decode_locked_numbers() {
+    let shelf = Shelf {
+        d: 4,
+        c: 3,
+        a: 1,
+        b: 2,
+    };
+
+    let mut buffer = Vec::new();
+    shelf.clone().to_protobuf(&mut buffer).unwrap();
+    // each field keeps its locked number, whatever its position.
+    assert_eq!(buffer, [8, 1, 16, 2, 24, 3, 32, 4]);
+    let decoded = Shelf::from_protobuf(&mut Cursor::new(buffer)).unwrap();
+    assert_eq!(shelf, decoded);
+}
@@ -2,15 +2,27 @@ use std::collections::HashSet;
 
 use heck::SnakeCase;
 use proc_macro2::TokenStream;
-use quote::quote;
 use quote::ToTokens;
-use syn::{self, Fields, FieldsNamed, Ident, ItemEnum, ItemStruct, Type, TypePath, Variant};
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{
+    self, Fields, FieldsNamed, FieldsUnnamed, Generics, Ident, Item, ItemEnum, ItemStruct, Member,
+    Type, TypePath, TypeReference, Variant,
+};
 
-use crate::extract::Extract;
+use crate::extract::{self, Collection, Extract};
 
 pub(crate) struct ConversionGenerator {
     pub(crate) token_stream: TokenStream,
     pub(crate) proxy_mod: TypePath,
+    /// The concrete type, such as `Page<Person>`, when converting an instantiation of a generic
+    /// item.
+    pub(crate) instance: Option<Type>,
+    /// The generics of the item, which are lifetimes only, as generic types are instantiated.
+    pub(crate) generics: Generics,
+    /// Whether the item holds references, which can be encoded but not decoded into.
+    pub(crate) borrowed: bool,
 }
 
 impl Extract for ConversionGenerator {
@@ -20,60 +32,88 @@ impl Extract for ConversionGenerator {
         fields_named: &FieldsNamed,
     ) {
         let ident = &item_struct.ident;
-        let proxy = &self.proxy_mod;
-
-        let (ref bindings, ref assignments) = self.generate_assignments(fields_named, true);
+        let proxy = self.proxy_mod.clone();
+        let (name, ty, generics) = self.rust_type(ident);
+        check_fields(ident, fields_named);
+        let members = &members(&item_struct.fields, fields_named);
+
+        let (ref bindings, ref assignments) =
+            self.generate_assignments(fields_named, members, true);
+        let encode = self.encode_borrowed(ident, fields_named, bindings, assignments);
+        let encode_to_vec = match &encode {
+            Some((encode, _)) => quote! {
+                let mut encoded = Vec::new();
+                let buffer = &mut encoded;
+                #encode?;
+                Ok(encoded)
+            },
+            None => quote! {
+                let proxy: #proxy::#ident = value.try_into()?;
+                Ok(prost::Message::encode_to_vec(&proxy))
+            },
+        };
 
         self.token_stream.extend(quote! {
-            impl TryFrom<#ident> for Option<#proxy::#ident> {
+            impl #generics TryFrom<#ty> for Option<#proxy::#ident> {
                 type Error = protobuf_gen::Error;
 
-                fn try_from(value: #ident) -> ::std::result::Result<Option<#proxy::#ident>, Self::Error> {
+                fn try_from(value: #ty) -> ::std::result::Result<Option<#proxy::#ident>, Self::Error> {
                     Ok(Some(value.try_into()?))
                 }
             }
 
-            impl TryFrom<#ident> for #proxy::#ident {
+            impl #generics TryFrom<#ty> for #proxy::#ident {
                 type Error = protobuf_gen::Error;
 
-                fn try_from(value: #ident) -> ::std::result::Result<#proxy::#ident, Self::Error> {
-                    let #ident { #(#bindings)* .. } = value;
+                fn try_from(value: #ty) -> ::std::result::Result<#proxy::#ident, Self::Error> {
+                    let #name { #(#bindings)* .. } = value;
                     Ok(#proxy::#ident {
                         #(#assignments)*
                     })
                 }
             }
 
-            impl TryFrom<#ident> for Vec<u8> {
+            impl #generics TryFrom<#ty> for Vec<u8> {
                 type Error = protobuf_gen::Error;
 
-                fn try_from(value: #ident) -> ::std::result::Result<Self, Self::Error> {
-                    let mut buffer = Vec::new();
-                    value.to_protobuf(&mut buffer)?;
-                    Ok(buffer)
+                fn try_from(value: #ty) -> ::std::result::Result<Self, Self::Error> {
+                    #encode_to_vec
                 }
             }
         });
 
-        let (ref bindings, ref assignments) = self.generate_assignments(fields_named, false);
-
-        let private_fields = if let Fields::Named(FieldsNamed { named, .. }) = &item_struct.fields {
-            let total_fields: HashSet<_> = named.iter().collect();
-            let proto_fields: HashSet<_> = fields_named.named.iter().collect();
-            (&total_fields - &proto_fields)
-                .into_iter()
-                .map(|f| {
-                    let ident = &f.ident;
-                    quote!(#ident: Default::default(),)
-                })
-                .collect()
-        } else {
-            Vec::new()
+        self.add_boxed_conversions(ident);
+        if self.borrowed {
+            // the proxy owns what the references would borrow, so they can only be encoded.
+            return;
+        }
+
+        let (ref bindings, ref assignments) =
+            self.generate_assignments(fields_named, members, false);
+
+        let private_fields: Vec<_> = match &item_struct.fields {
+            Fields::Named(FieldsNamed { named, .. }) => {
+                let total_fields: HashSet<_> = named.iter().collect();
+                let proto_fields: HashSet<_> = fields_named.named.iter().collect();
+                (&total_fields - &proto_fields)
+                    .into_iter()
+                    .map(|f| {
+                        let ident = &f.ident;
+                        quote!(#ident: Default::default(),)
+                    })
+                    .collect()
+            }
+            Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => (0..unnamed.len())
+                .map(|index| Member::Unnamed(index.into()))
+                .filter(|member| !members.contains(member))
+                .map(|member| quote!(#member: Default::default(),))
+                .collect(),
+            Fields::Unit => Vec::new(),
         };
         let private_fields = &private_fields;
 
         self.token_stream.extend(quote! {
-            impl TryFrom<Option<#proxy::#ident>> for #ident {
+            impl #generics TryFrom<Option<#proxy::#ident>> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(other: Option<#proxy::#ident>) -> ::std::result::Result<Self, Self::Error> {
@@ -86,7 +126,7 @@ impl Extract for ConversionGenerator {
                 }
             }
 
-            impl TryFrom<#proxy::#ident> for #ident {
+            impl #generics TryFrom<#proxy::#ident> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(#proxy::#ident { #(#bindings)* }: #proxy::#ident) -> ::std::result::Result<Self, Self::Error> {
@@ -97,7 +137,7 @@ impl Extract for ConversionGenerator {
                 }
             }
 
-            impl TryFrom<Vec<u8>> for #ident {
+            impl #generics TryFrom<Vec<u8>> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(value: Vec<u8>) -> ::std::result::Result<Self, Self::Error> {
@@ -106,7 +146,8 @@ impl Extract for ConversionGenerator {
             }
         });
 
-        self.add_derive_protobuf_gen(ident);
+        self.add_well_known_conversions(ident, false);
+        self.add_derive_protobuf_gen(ident, encode);
     }
 
     fn extract_nested_message_with_fields_named(
@@ -115,40 +156,60 @@ impl Extract for ConversionGenerator {
         variant: &Variant,
         fields_named: &FieldsNamed,
     ) {
+        if self.borrowed {
+            return;
+        }
+
         let ident = &item_enum.ident;
         let proxy = &self.proxy_mod;
+        let (name, ty, generics) = self.rust_type(ident);
         let inner_mod: Ident = syn::parse_str(&ident.to_string().to_snake_case()).unwrap();
+        let members = members(&variant.fields, fields_named);
         let variant = &variant.ident;
         let variant_inner: Ident = syn::parse_str(&format!("{}Inner", variant)).unwrap();
+        check_fields(variant, fields_named);
 
-        let (bindings, assignments) = self.generate_assignments(fields_named, false);
+        let (bindings, assignments) = self.generate_assignments(fields_named, &members, false);
 
         self.token_stream.extend(quote! {
-            impl TryFrom<#proxy::#inner_mod::#variant_inner> for #ident {
+            impl #generics TryFrom<#proxy::#inner_mod::#variant_inner> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(#proxy::#inner_mod::#variant_inner { #(#bindings)* }: #proxy::#inner_mod::#variant_inner) -> ::std::result::Result<Self, Self::Error> {
-                    Ok(#ident::#variant {
+                    Ok(#name::#variant {
                         #(#assignments)*
                     })
                 }
             }
+
+            impl #generics TryFrom<Box<#proxy::#inner_mod::#variant_inner>> for #ty {
+                type Error = protobuf_gen::Error;
+
+                fn try_from(other: Box<#proxy::#inner_mod::#variant_inner>) -> ::std::result::Result<Self, Self::Error> {
+                    (*other).try_into()
+                }
+            }
         });
     }
 
     fn extract_nested_message_with_fields_unit(&mut self, item_enum: &ItemEnum, variant: &Variant) {
+        if self.borrowed {
+            return;
+        }
+
         let ident = &item_enum.ident;
         let proxy = &self.proxy_mod;
+        let (name, ty, generics) = self.rust_type(ident);
         let inner_mod: Ident = syn::parse_str(&ident.to_string().to_snake_case()).unwrap();
         let variant = &variant.ident;
         let variant_inner: Ident = syn::parse_str(&format!("{}Inner", variant)).unwrap();
 
         self.token_stream.extend(quote! {
-            impl TryFrom<#proxy::#inner_mod::#variant_inner> for #ident {
+            impl #generics TryFrom<#proxy::#inner_mod::#variant_inner> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(_: #proxy::#inner_mod::#variant_inner) -> ::std::result::Result<Self, Self::Error> {
-                    Ok(#ident::#variant {})
+                    Ok(#name::#variant {})
                 }
             }
         });
@@ -156,83 +217,116 @@ impl Extract for ConversionGenerator {
 
     fn extract_one_of(&mut self, item_enum: &ItemEnum) {
         let ident = &item_enum.ident;
-        let proxy = &self.proxy_mod;
+        let proxy = self.proxy_mod.clone();
+        let (name, ty, generics) = self.rust_type(ident);
         let inner_mod: Ident = syn::parse_str(&ident.to_string().to_snake_case()).unwrap();
+        if let Err(e) = extract::check_field_tags(
+            item_enum.variants.iter().map(|v| (v.ident.to_string(), v.attrs.as_slice())),
+        ) {
+            panic!("invalid field numbers in \"{}\": {}", ident, e);
+        }
 
         let cases = item_enum.variants.iter().map(|v| {
             let variant = &v.ident;
             let variant_inner: Ident = syn::parse_str(&format!("{}Inner", variant)).unwrap();
             match &v.fields {
                 Fields::Unit => quote!{
-                    #ident::#variant {} => #proxy::#ident {
+                    #name::#variant {} => #proxy::#ident {
                         inner: Some(#proxy::#inner_mod::Inner::#variant(#proxy::#inner_mod::#variant_inner {})),
                     },
                 },
-                Fields::Named(fields_named) => {
-                    let (bindings, assignments) = self.generate_assignments(fields_named, true);
+                Fields::Unnamed(fields_unnamed) if fields_unnamed.unnamed.len() == 1 => {
+                    let convert_inner =
+                        self.convert(quote!(inner), Some(&fields_unnamed.unnamed[0].ty), variant, true);
+                    quote!{
+                        #name::#variant(inner) => #proxy::#ident {
+                            inner: Some(#proxy::#inner_mod::Inner::#variant(#convert_inner?)),
+                        },
+                    }
+                },
+                Fields::Named(_) | Fields::Unnamed(_) => {
+                    let fields_named = match &v.fields {
+                        Fields::Unnamed(fields_unnamed) => extract::name_tuple_fields(fields_unnamed)
+                            .unwrap_or_else(|e| panic!("invalid variant \"{}\" in \"{}\": {}", variant, ident, e)),
+                        Fields::Named(fields_named) => fields_named.clone(),
+                        Fields::Unit => unreachable!(),
+                    };
+                    let (bindings, assignments) =
+                        self.generate_assignments(&fields_named, &members(&v.fields, &fields_named), true);
+                    // prost boxes the variant if it is recursive, hence the `into`.
                     quote!{
-                        #ident::#variant { #(#bindings)* } => #proxy::#ident {
+                        #name::#variant { #(#bindings)* } => #proxy::#ident {
                             inner: Some(#proxy::#inner_mod::Inner::#variant(#proxy::#inner_mod::#variant_inner {
                                 #(#assignments)*
-                            })),
+                            }.into())),
                         },
                     }
                 },
-                Fields::Unnamed(_) => quote!{
-                    #ident::#variant(inner) => #proxy::#ident {
-                        inner: Some(#proxy::#inner_mod::Inner::#variant(
-                            inner.try_into().map_err(|e| protobuf_gen::Error::new_try_from_error(stringify!(#proxy::#ident), e))?
-                        )),
-                    },
-                },
             }
         }).collect::<Vec<_>>();
         let cases = &cases;
 
         self.token_stream.extend(quote! {
-            impl TryFrom<#ident> for #proxy::#ident {
+            impl #generics TryFrom<#ty> for #proxy::#ident {
                 type Error = protobuf_gen::Error;
 
-                fn try_from(value: #ident) -> ::std::result::Result<#proxy::#ident, Self::Error> {
+                fn try_from(value: #ty) -> ::std::result::Result<#proxy::#ident, Self::Error> {
                     Ok(match value {
                         #(#cases)*
                     })
                 }
             }
 
-            impl TryFrom<#ident> for Option<#proxy::#ident> {
+            impl #generics TryFrom<#ty> for Option<#proxy::#ident> {
                 type Error = protobuf_gen::Error;
 
-                fn try_from(value: #ident) -> ::std::result::Result<Option<#proxy::#ident>, Self::Error> {
+                fn try_from(value: #ty) -> ::std::result::Result<Option<#proxy::#ident>, Self::Error> {
                     Ok(Some(value.try_into()?))
                 }
             }
 
-            impl TryFrom<#ident> for Vec<u8> {
+            impl #generics TryFrom<#ty> for Vec<u8> {
                 type Error = protobuf_gen::Error;
 
-                fn try_from(value: #ident) -> ::std::result::Result<Self, Self::Error> {
-                    let mut buffer = Vec::new();
-                    value.to_protobuf(&mut buffer)?;
-                    Ok(buffer)
+                fn try_from(value: #ty) -> ::std::result::Result<Self, Self::Error> {
+                    let proxy: #proxy::#ident = value.try_into()?;
+                    Ok(prost::Message::encode_to_vec(&proxy))
                 }
             }
         });
 
+        self.add_boxed_conversions(ident);
+        if self.borrowed {
+            return;
+        }
+
         let cases = item_enum
             .variants
             .iter()
             .map(|v| {
                 let variant = &v.ident;
-                quote!(#proxy::#inner_mod::Inner::#variant(inner) =>
-                    inner.try_into().map_err(|e| protobuf_gen::Error::new_try_from_error(stringify!(#proxy::#ident), e)),
-                )
+                match &v.fields {
+                    Fields::Unnamed(fields_unnamed) if fields_unnamed.unnamed.len() == 1 => {
+                        let convert_inner = self.convert(
+                            quote!(inner),
+                            Some(&fields_unnamed.unnamed[0].ty),
+                            variant,
+                            false,
+                        );
+                        quote!(
+                            #proxy::#inner_mod::Inner::#variant(inner) => Ok(#name::#variant(#convert_inner?)),
+                        )
+                    }
+                    _ => quote!(#proxy::#inner_mod::Inner::#variant(inner) =>
+                        inner.try_into().map_err(|e| protobuf_gen::Error::new_try_from_error(stringify!(#proxy::#ident), e)),
+                    ),
+                }
             })
             .collect::<Vec<_>>();
         let cases = &cases;
 
         self.token_stream.extend(quote! {
-            impl TryFrom<#proxy::#ident> for #ident {
+            impl #generics TryFrom<#proxy::#ident> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(#proxy::#ident { inner }: #proxy::#ident) -> ::std::result::Result<Self, Self::Error> {
@@ -246,7 +340,7 @@ impl Extract for ConversionGenerator {
                 }
             }
 
-            impl TryFrom<Option<#proxy::#ident>> for #ident {
+            impl #generics TryFrom<Option<#proxy::#ident>> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(other: Option<#proxy::#ident>) -> ::std::result::Result<Self, Self::Error> {
@@ -262,7 +356,7 @@ impl Extract for ConversionGenerator {
                 }
             }
 
-            impl TryFrom<Vec<u8>> for #ident {
+            impl #generics TryFrom<Vec<u8>> for #ty {
                 type Error = protobuf_gen::Error;
 
                 fn try_from(value: Vec<u8>) -> ::std::result::Result<Self, Self::Error> {
@@ -271,16 +365,25 @@ impl Extract for ConversionGenerator {
             }
         });
 
-        self.add_derive_protobuf_gen(ident);
+        self.add_well_known_conversions(ident, false);
+        self.add_derive_protobuf_gen(ident, None);
+    }
+
+    fn extract_error(&mut self, _: &Ident, error: syn::Error) {
+        self.token_stream.extend(error.to_compile_error());
     }
 
     fn extract_enumerator(&mut self, item_enum: &ItemEnum) {
         let ident = &item_enum.ident;
         let proxy = &self.proxy_mod;
+        if let Err(e) = extract::enum_values(item_enum) {
+            panic!("invalid enum values in \"{}\": {}", ident, e);
+        }
 
         let cases = item_enum.variants.iter().map(|v| {
             let variant = &v.ident;
-            quote!(#ident::#variant => #proxy::#ident::#variant,)
+            let proxy_variant = proxy_variant(ident, variant);
+            quote!(#ident::#variant => #proxy::#ident::#proxy_variant,)
         });
 
         self.token_stream.extend(quote! {
@@ -319,7 +422,8 @@ impl Extract for ConversionGenerator {
 
         let cases = item_enum.variants.iter().map(|v| {
             let variant = &v.ident;
-            quote!(#proxy::#ident::#variant => #ident::#variant,)
+            let proxy_variant = proxy_variant(ident, variant);
+            quote!(#proxy::#ident::#proxy_variant => #ident::#variant,)
         });
 
         self.token_stream.extend(quote! {
@@ -397,30 +501,131 @@ impl Extract for ConversionGenerator {
                 }
             }
         });
+
+        self.add_well_known_conversions(ident, true);
     }
 }
 
 impl ConversionGenerator {
-    fn add_derive_protobuf_gen<T>(&mut self, ident: T)
-    where
-        T: ToTokens,
-    {
+    /// Returns the name and the type of the Rust item converted to and from the proxy `ident`,
+    /// which differ from `ident` for instantiations of generic items, and the generics of the
+    /// conversion impls.
+    fn rust_type(&self, ident: &Ident) -> (Ident, TokenStream, TokenStream) {
+        let (impl_generics, ty_generics, _) = self.generics.split_for_impl();
+        let ty = match &self.instance {
+            Some(ty) => ty.to_token_stream(),
+            None => quote!(#ident #ty_generics),
+        };
+        // instances are checked to be named types when the item is instantiated.
+        let name =
+            self.instance.as_ref().and_then(|ty| extract::type_ident(ty).ok()).unwrap_or(ident);
+        (name.clone(), ty, impl_generics.to_token_stream())
+    }
+
+    /// Converts into and from boxed proxies, which prost generates for recursive fields.
+    fn add_boxed_conversions(&mut self, ident: &Ident) {
         let proxy = &self.proxy_mod;
+        let (_, ty, generics) = self.rust_type(ident);
 
         self.token_stream.extend(quote! {
-            impl ProtobufGen for #ident {
+            impl #generics TryFrom<#ty> for Box<#proxy::#ident> {
                 type Error = protobuf_gen::Error;
 
-                fn to_protobuf<B: protobuf_gen::bytes::BufMut>(self, buffer: &mut B) -> ::std::result::Result<(), Self::Error> {
+                fn try_from(value: #ty) -> ::std::result::Result<Box<#proxy::#ident>, Self::Error> {
+                    Ok(Box::new(value.try_into()?))
+                }
+            }
+
+            impl #generics TryFrom<#ty> for Option<Box<#proxy::#ident>> {
+                type Error = protobuf_gen::Error;
+
+                fn try_from(value: #ty) -> ::std::result::Result<Option<Box<#proxy::#ident>>, Self::Error> {
+                    Ok(Some(value.try_into()?))
+                }
+            }
+        });
+        if self.borrowed {
+            return;
+        }
+
+        self.token_stream.extend(quote! {
+            impl #generics TryFrom<Box<#proxy::#ident>> for #ty {
+                type Error = protobuf_gen::Error;
+
+                fn try_from(other: Box<#proxy::#ident>) -> ::std::result::Result<Self, Self::Error> {
+                    (*other).try_into()
+                }
+            }
+
+            impl #generics TryFrom<Option<Box<#proxy::#ident>>> for #ty {
+                type Error = protobuf_gen::Error;
+
+                fn try_from(other: Option<Box<#proxy::#ident>>) -> ::std::result::Result<Self, Self::Error> {
+                    other.map(|x| *x).try_into()
+                }
+            }
+        });
+    }
+
+    /// Converts through `protobuf_gen::WellKnownType` if the type is named like a well-known type,
+    /// as the conversions of fields of such a name cannot tell which type it refers to.
+    fn add_well_known_conversions(&mut self, ident: &Ident, enumerator: bool) {
+        let proxy = &self.proxy_mod;
+        let (name, ty, generics) = self.rust_type(ident);
+        if !extract::has_well_known_name(&syn::parse_quote!(#name)) {
+            return;
+        }
+
+        // enums are stored as their `i32` values in the proxy.
+        let proxy_type = if enumerator { quote!(i32) } else { quote!(#proxy::#ident) };
+        self.token_stream.extend(quote! {
+            impl #generics protobuf_gen::WellKnownType for #ty {
+                type Proxy = #proxy_type;
+
+                fn into_proxy(self) -> ::std::result::Result<Self::Proxy, protobuf_gen::Error> {
+                    self.try_into()
+                }
+
+                fn from_proxy(proxy: Self::Proxy) -> ::std::result::Result<Self, protobuf_gen::Error> {
+                    proxy.try_into()
+                }
+            }
+        });
+    }
+
+    /// Implements `ProtobufGen`, encoding through the proxy unless `encode` gives the raw and the
+    /// length-delimited encodings of a message with borrowed fields.
+    fn add_derive_protobuf_gen(
+        &mut self,
+        ident: &Ident,
+        encode: Option<(TokenStream, TokenStream)>,
+    ) {
+        let proxy = &self.proxy_mod;
+        let (_, ty, generics) = self.rust_type(ident);
+        let (encode, encode_length_delimited) = encode.unwrap_or_else(|| {
+            let encode = |method: TokenStream| {
+                quote! {
                     use prost::Message;
 
-                    let proxy: #proxy::#ident = self.try_into().map_err(|e| {
+                    let proxy: #proxy::#ident = value.try_into().map_err(|e| {
                         protobuf_gen::Error::new_try_from_error(stringify!(#proxy::#ident), e)
                     })?;
 
-                    proxy.encode(buffer)?;
+                    proxy.#method(buffer)?;
                     Ok(())
                 }
+            };
+            (encode(quote!(encode)), encode(quote!(encode_length_delimited)))
+        });
+
+        self.token_stream.extend(quote! {
+            impl #generics ProtobufGen for #ty {
+                type Error = protobuf_gen::Error;
+
+                fn to_protobuf<B: protobuf_gen::bytes::BufMut>(self, buffer: &mut B) -> ::std::result::Result<(), Self::Error> {
+                    let value = self;
+                    #encode
+                }
 
                 fn from_protobuf<B: protobuf_gen::bytes::Buf>(buffer: B) -> ::std::result::Result<Self, Self::Error> {
                     let proxy: #proxy::#ident = prost::Message::decode(buffer)?;
@@ -430,14 +635,8 @@ impl ConversionGenerator {
                 }
 
                 fn to_protobuf_length_delimited<B: protobuf_gen::bytes::BufMut>(self, buffer: &mut B) -> ::std::result::Result<(), Self::Error> {
-                    use prost::Message;
-
-                    let proxy: #proxy::#ident = self.try_into().map_err(|e| {
-                        protobuf_gen::Error::new_try_from_error(stringify!(#proxy::#ident), e)
-                    })?;
-
-                    proxy.encode_length_delimited(buffer)?;
-                    Ok(())
+                    let value = self;
+                    #encode_length_delimited
                 }
 
                 fn from_protobuf_length_delimited<B: protobuf_gen::bytes::Buf>(buffer: B) -> ::std::result::Result<Self, Self::Error> {
@@ -450,69 +649,130 @@ impl ConversionGenerator {
         });
     }
 
+    /// Returns the raw and the length-delimited encodings of `value`, a message with fields
+    /// borrowing strings or bytes, or `None` if it has no such fields. The borrowed fields are
+    /// left empty in the proxy and written after it, from where they are borrowed, with the
+    /// numbers the generator puts into the proxy. `bindings` and `assignments` convert into the
+    /// proxy.
+    fn encode_borrowed(
+        &self,
+        ident: &Ident,
+        fields_named: &FieldsNamed,
+        bindings: &[TokenStream],
+        assignments: &[TokenStream],
+    ) -> Option<(TokenStream, TokenStream)> {
+        let proxy = &self.proxy_mod;
+        let (name, _, _) = self.rust_type(ident);
+        let borrowed: Vec<_> = fields_named.named.iter().map(extract::borrows_bytes).collect();
+        if !borrowed.contains(&true) {
+            return None;
+        }
+
+        let fields: Vec<_> = fields_named
+            .named
+            .iter()
+            .zip(&borrowed)
+            .filter(|(_, borrowed)| **borrowed)
+            .map(|(x, _)| x.ident.as_ref().unwrap())
+            .collect();
+        let tags: Vec<_> = fields
+            .iter()
+            .map(|field| format_ident!("{}", extract::field_tag_name(&field.to_string())))
+            .collect();
+        let assignments = assignments
+            .iter()
+            .zip(&borrowed)
+            .filter(|(_, borrowed)| !**borrowed)
+            .map(|(assignment, _)| assignment);
+        // the names bound here start with underscores so as not to shadow the fields.
+        let encode = quote! {
+            let __buffer = buffer;
+            let #name { #(#bindings)* .. } = value;
+            let __proxy = #proxy::#ident {
+                #(#assignments)*
+                ..Default::default()
+            };
+            let __len = prost::Message::encoded_len(&__proxy)
+                #(+ protobuf_gen::BorrowedField::encoded_len(&#fields, #proxy::#ident::#tags))*;
+        };
+        let check_capacity = quote! {
+            let __remaining = protobuf_gen::bytes::BufMut::remaining_mut(__buffer);
+            if __required > __remaining {
+                return Err(protobuf_gen::Error::new_insufficient_buffer(__required, __remaining));
+            }
+        };
+        let encode_fields = quote! {
+            prost::Message::encode_raw(&__proxy, __buffer);
+            #(protobuf_gen::BorrowedField::encode(&#fields, #proxy::#ident::#tags, __buffer);)*
+            Ok::<(), protobuf_gen::Error>(())
+        };
+
+        Some((
+            quote!({
+                #encode
+                let __required = __len;
+                #check_capacity
+                #encode_fields
+            }),
+            quote!({
+                #encode
+                let __required = __len + prost::encoding::encoded_len_varint(__len as u64);
+                #check_capacity
+                prost::encoding::encode_varint(__len as u64, __buffer);
+                #encode_fields
+            }),
+        ))
+    }
+
+    /// Generates the bindings destructuring the source and the assignments building the target
+    /// of a conversion. `members` are the fields of `fields_named` in the Rust type, which are
+    /// positions for tuple structs.
     fn generate_assignments(
         &self,
         fields_named: &FieldsNamed,
+        members: &[Member],
         into_proxy: bool,
     ) -> (Vec<TokenStream>, Vec<TokenStream>) {
         let bindings = fields_named
             .named
             .iter()
-            .map(|x| {
+            .zip(members)
+            .map(|(x, member)| {
                 let field = x.ident.as_ref().unwrap();
-                quote!(#field,)
+                match member {
+                    Member::Unnamed(_) if into_proxy => quote!(#member: #field,),
+                    _ => quote!(#field,),
+                }
             })
             .collect();
 
         let assignments = fields_named
             .named
             .iter()
-            .map(|x| {
+            .zip(members)
+            .map(|(x, member)| {
                 let field = x.ident.as_ref().unwrap();
-                if let Type::Path(type_path) = &x.ty {
-                    let type_ident = &type_path.path.segments.last().unwrap().ident;
-                    if type_ident == "Vec"
-                        || type_ident == "HashSet"
-                        || type_ident == "IndexMap"
-                        || type_ident == "IndexSet"
-                    {
-                        return quote!(
-                            #field : #field.into_iter().map(|x|
-                                x.try_into().map_err(|e|
-                                    protobuf_gen::Error::new_try_from_error(stringify!(#field).to_string(), e)
-                                )
-                            ).collect::<::std::result::Result<_, protobuf_gen::Error>>()?,
-                        );
-                    } else if type_ident == "HashMap" {
-                        return quote!(
-                            #field : #field.into_iter().map(|(k, v)| {
-                                let k = k.try_into().map_err(|e|
-                                    protobuf_gen::Error::new_try_from_error(stringify!(#field).to_string(), e)
-                                )?;
-
-                                let v = v.try_into().map_err(|e|
-                                    protobuf_gen::Error::new_try_from_error(stringify!(#field).to_string(), e)
-                                )?;
-
-                                Ok((k, v))
-                            }).collect::<::std::result::Result<_, protobuf_gen::Error>>()?,
-                        );
-                    }
-                    else if type_ident == "Option" {
-                        return quote!(
-                            #field : #field.map(|v| {
-                                v.try_into().map_err(|e|
-                                    protobuf_gen::Error::new_try_from_error(stringify!(#field).to_string(), e)
-                                )
-                            }).transpose()?,
-                        );
-                    }
+                let target = if into_proxy { quote!(#field) } else { quote!(#member) };
+
+                // message fields are optional in the proxy, so missing values become defaults.
+                if extract::has_well_known_name(&x.ty) {
+                    return if into_proxy {
+                        quote!(#target : protobuf_gen::WellKnownField::from_value(protobuf_gen::WellKnownType::into_proxy(#field)?),)
+                    } else {
+                        quote!(#target : protobuf_gen::WellKnownType::from_proxy(protobuf_gen::WellKnownField::into_value(#field))?,)
+                    };
+                }
+
+                if let Some(convert_collection) =
+                    self.convert_collection(quote!(#field), &x.ty, field, into_proxy)
+                {
+                    return quote!(#target : #convert_collection?,);
                 }
 
                 if syn_util::contains_attribute(&x.attrs, &["protobuf_gen", "opaque"]) {
                     return if into_proxy {
                         quote!(
-                            #field : {
+                            #target : {
                                 let mut buffer = Vec::new();
                                 #field.to_protobuf(&mut buffer)?;
                                 buffer
@@ -521,17 +781,239 @@ impl ConversionGenerator {
                     }
                     else {
                         quote!(
-                            #field : ProtobufGen::from_protobuf(&mut std::io::Cursor::new(#field))?,
+                            #target : ProtobufGen::from_protobuf(&mut std::io::Cursor::new(#field))?,
                         )
                     };
                 }
 
-                quote!(
-                    #field : #field.try_into().map_err(|e| protobuf_gen::Error::new_try_from_error(stringify!(#field).to_string(), e))?,
-                )
+                let convert_field = self.convert(quote!(#field), Some(&x.ty), field, into_proxy);
+                quote!(#target : #convert_field?,)
             })
             .collect();
 
         (bindings, assignments)
     }
+
+    /// Converts `value` of collection type `ty` into or from its proxy element by element, or
+    /// returns `None` if `ty` is not a collection.
+    fn convert_collection(
+        &self,
+        value: TokenStream,
+        ty: &Type,
+        field: &Ident,
+        into_proxy: bool,
+    ) -> Option<TokenStream> {
+        let collection = Collection::of(ty)?;
+        let element = extract::element_type(ty);
+        let convert_element = self.convert(quote!(x), element, field, into_proxy);
+        let (key, map_value) = extract::map_types(ty).unzip();
+        let convert_key = self.convert(quote!(k), key, field, into_proxy);
+        let convert_value = self.convert(quote!(v), map_value, field, into_proxy);
+        Some(match (collection, ty) {
+            (Collection::Array, Type::Array(type_array)) if !into_proxy => {
+                let len = &type_array.len;
+                quote!(
+                    #value.into_iter().map(|x|
+                        #convert_element
+                    ).collect::<::std::result::Result<Vec<_>, protobuf_gen::Error>>()
+                    .and_then(|v| v.try_into().map_err(|v: Vec<_>|
+                        protobuf_gen::Error::new_invalid_length(stringify!(#field), #len, v.len())
+                    ))
+                )
+            }
+            (Collection::Array | Collection::Repeated, _) => quote!(
+                #value.into_iter().map(|x|
+                    #convert_element
+                ).collect::<::std::result::Result<_, protobuf_gen::Error>>()
+            ),
+            (Collection::Map, _) => quote!(
+                #value.into_iter().map(|(k, v)| {
+                    let k = #convert_key?;
+
+                    let v = #convert_value?;
+
+                    Ok((k, v))
+                }).collect::<::std::result::Result<_, protobuf_gen::Error>>()
+            ),
+            (Collection::Optional, _) => quote!(
+                #value.map(|x| {
+                    #convert_element
+                }).transpose()
+            ),
+        })
+    }
+
+    /// Converts `value` of type `ty` into or from its proxy. Borrowed values are copied into the
+    /// proxy, which messages encoding them skip, and decoded into `Cow::Owned`. Pointers convert
+    /// what they point to, cloned out of shared `Rc`s and `Arc`s through
+    /// `protobuf_gen::SharedPointer`, and collections nested in another are wrapped in the
+    /// messages named by `extract::wrapper_name`. Types named like well-known types are converted through
+    /// `protobuf_gen::WellKnownType`, which messages of such a name implement too. Byte arrays
+    /// and 128-bit integers, which are `bytes` in the proxy, check the length of the decoded
+    /// buffer, `NonZero` integers go through their base type, and chars are checked to be valid.
+    fn convert(
+        &self,
+        value: TokenStream,
+        ty: Option<&Type>,
+        field: &Ident,
+        into_proxy: bool,
+    ) -> TokenStream {
+        let try_from_error =
+            quote!(|e| protobuf_gen::Error::new_try_from_error(stringify!(#field).to_string(), e));
+        let length_error = |expected: TokenStream| quote!(|v: Vec<u8>| protobuf_gen::Error::new_invalid_length(stringify!(#field), #expected, v.len()));
+
+        let ty = match ty {
+            Some(ty) => ty,
+            None => return quote!(#value.try_into().map_err(#try_from_error)),
+        };
+        if let Some(borrowed) = extract::borrowed(ty) {
+            return if into_proxy {
+                let owned = match ty {
+                    Type::Reference(_) => quote!(#value.to_owned()),
+                    _ => quote!(#value.into_owned()),
+                };
+                self.convert(owned, Some(borrowed), field, true)
+            } else {
+                let convert_owned = self.convert(value, Some(borrowed), field, false);
+                quote!(#convert_owned.map(::std::borrow::Cow::Owned))
+            };
+        }
+        if let Some(pointee) = extract::pointee(ty) {
+            return if into_proxy {
+                let pointer = extract::type_ident(ty).map(Ident::to_string);
+                let pointee_value = match pointer.as_deref() {
+                    // spanned so that a pointee which is not `Clone` is reported at the field.
+                    Ok("Rc" | "Arc") => quote_spanned! {ty.span()=>
+                        protobuf_gen::SharedPointer::into_pointee(#value)
+                    },
+                    _ => quote!((*#value)),
+                };
+                self.convert(pointee_value, Some(pointee), field, true)
+            } else {
+                let convert_pointee = self.convert(value, Some(pointee), field, false);
+                quote!(#convert_pointee.map(<#ty>::new))
+            };
+        }
+        if let Some(collection) = Collection::of(ty) {
+            let proxy = &self.proxy_mod;
+            let wrapper = extract::wrapper_name(ty)
+                .unwrap_or_else(|e| panic!("invalid field \"{}\": {}", field, e));
+            let wrapper = format_ident!("{}", wrapper);
+            let wrapper_field = format_ident!("{}", collection.wrapper_field());
+            return if into_proxy {
+                let convert_collection = self.convert_collection(value, ty, field, true);
+                quote!(#convert_collection.map(|#wrapper_field| #proxy::#wrapper { #wrapper_field }))
+            } else {
+                let convert_collection =
+                    self.convert_collection(quote!(#wrapper_field), ty, field, false);
+                quote!({
+                    let #proxy::#wrapper { #wrapper_field } = #value;
+                    #convert_collection
+                })
+            };
+        }
+        if extract::has_well_known_name(ty) {
+            return if into_proxy {
+                quote!(protobuf_gen::WellKnownType::into_proxy(#value))
+            } else {
+                quote!(protobuf_gen::WellKnownType::from_proxy(#value))
+            };
+        }
+        if let Some(base) = extract::non_zero_base(ty) {
+            return if into_proxy {
+                quote!(#value.get().try_into().map_err(#try_from_error))
+            } else {
+                quote!(
+                    #value.try_into().map_err(#try_from_error).and_then(|v: #base|
+                        <#ty>::new(v).ok_or_else(|| protobuf_gen::Error::new_zero_value(stringify!(#field)))
+                    )
+                )
+            };
+        }
+        if is_128_bit(ty) {
+            return if into_proxy {
+                quote!(Ok::<_, protobuf_gen::Error>(#value.to_le_bytes().to_vec()))
+            } else {
+                let length_error = length_error(quote!(16));
+                quote!(<[u8; 16]>::try_from(#value).map(<#ty>::from_le_bytes).map_err(#length_error))
+            };
+        }
+        match ty {
+            Type::Array(type_array) if !into_proxy && extract::is_bytes(ty) => {
+                let length_error = length_error(type_array.len.to_token_stream());
+                quote!(#value.try_into().map_err(#length_error))
+            }
+            Type::Path(type_path) if !into_proxy && type_path.path.is_ident("char") => quote!({
+                let v: u32 = #value;
+                char::from_u32(v).ok_or_else(|| protobuf_gen::Error::new_invalid_char(stringify!(#field), v))
+            }),
+            _ => quote!(#value.try_into().map_err(#try_from_error)),
+        }
+    }
+}
+
+/// Returns whether `ty` is `u128` or `i128`, which are little-endian `bytes` in the proxy.
+fn is_128_bit(ty: &Type) -> bool {
+    matches!(ty, Type::Path(type_path) if type_path.path.is_ident("u128") || type_path.path.is_ident("i128"))
+}
+
+/// Returns whether `item` holds references such as `&'a str`.
+pub(crate) fn has_references(item: &Item) -> bool {
+    struct References(bool);
+
+    impl<'ast> Visit<'ast> for References {
+        fn visit_type_reference(&mut self, _: &'ast TypeReference) {
+            self.0 = true;
+        }
+    }
+
+    let mut references = References(false);
+    references.visit_item(item);
+    references.0
+}
+
+/// Returns the members of the Rust type holding `fields_named`, which are positions for tuple
+/// structs named by `extract::name_tuple_fields`.
+fn members(fields: &Fields, fields_named: &FieldsNamed) -> Vec<Member> {
+    fields_named
+        .named
+        .iter()
+        .map(|x| {
+            let field = x.ident.as_ref().unwrap();
+            match fields {
+                Fields::Unnamed(fields_unnamed) => fields_unnamed
+                    .unnamed
+                    .iter()
+                    .enumerate()
+                    .find(|(index, f)| {
+                        extract::tuple_field_ident(*index, f).ok().as_ref() == Some(field)
+                    })
+                    .map(|(index, _)| Member::Unnamed(index.into()))
+                    .unwrap(),
+                _ => Member::Named(field.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Checks the attributes of the fields. Their numbers are left to the generator, which puts them
+/// into the proxies, apart from the explicit tags.
+fn check_fields(ident: &Ident, fields_named: &FieldsNamed) {
+    if let Err(e) = extract::check_field_tags(
+        fields_named
+            .named
+            .iter()
+            .map(|x| (x.ident.as_ref().unwrap().to_string(), x.attrs.as_slice())),
+    ) {
+        panic!("invalid field numbers in \"{}\": {}", ident, e);
+    }
+    for field in &fields_named.named {
+        if let Err(e) = extract::integer_encoding(&field.attrs) {
+            panic!("invalid field \"{}\" in \"{}\": {}", field.ident.as_ref().unwrap(), ident, e);
+        }
+    }
+}
+
+fn proxy_variant(ident: &Ident, variant: &Ident) -> Ident {
+    syn::parse_str(&extract::proxy_variant_name(&ident.to_string(), &variant.to_string())).unwrap()
 }
@@ -6,10 +6,9 @@ mod convert;
 
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
-use syn::{Fields, Item, ItemEnum, ItemStruct, TypePath};
+use syn::{Generics, Item, ItemEnum, ItemStruct, TypePath};
 
 use convert::ConversionGenerator;
-use extract::Extract;
 
 #[proc_macro_derive(ProtobufGen, attributes(protobuf_gen))]
 pub fn derive_protobuf_gen(input: TokenStream) -> TokenStream {
@@ -35,24 +34,28 @@ pub fn derive_protobuf_gen(input: TokenStream) -> TokenStream {
 }
 
 fn generate_conversion_apis(item: &Item, proxy_mod: TypePath) -> TokenStream2 {
-    let mut builder = ConversionGenerator { token_stream: TokenStream2::default(), proxy_mod };
-
-    match item {
-        Item::Struct(item_struct) => {
-            protobuf_gen_extract::extract_message(&mut builder, item_struct);
-        }
-        Item::Enum(item_enum) => {
-            if item_enum.variants.iter().all(|v| matches!(v.fields, Fields::Unit)) {
-                builder.extract_enumerator(item_enum);
-            } else {
-                builder.extract_one_of(item_enum);
-
-                for variant in &item_enum.variants {
-                    protobuf_gen_extract::extract_nested_message(&mut builder, item_enum, variant);
-                }
+    let mut builder = ConversionGenerator {
+        token_stream: TokenStream2::default(),
+        proxy_mod,
+        instance: None,
+        generics: Generics::default(),
+        borrowed: false,
+    };
+
+    let instances = match extract::instantiate(item) {
+        Ok(instances) => instances,
+        Err(error) => return error.to_compile_error(),
+    };
+    for (item, instance) in instances {
+        builder.instance = instance;
+        builder.generics = match &item {
+            Item::Struct(ItemStruct { generics, .. }) | Item::Enum(ItemEnum { generics, .. }) => {
+                generics.clone()
             }
-        }
-        _ => unreachable!(),
+            _ => Generics::default(),
+        };
+        builder.borrowed = convert::has_references(&item);
+        extract::extract_item(&mut builder, &item);
     }
 
     builder.token_stream
@@ -1,8 +1,318 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use heck::CamelCase;
+use quote::{format_ident, ToTokens};
+use syn::visit_mut::{self, VisitMut};
 use syn::{
-    Fields, FieldsNamed, File, Item, ItemEnum, ItemStruct, Meta, MetaList, NestedMeta, Path,
-    Variant,
+    Attribute, Expr, ExprLit, ExprUnary, Fields, FieldsNamed, FieldsUnnamed, File, GenericArgument,
+    Ident, Item, ItemEnum, ItemStruct, Lit, Meta, MetaList, MetaNameValue, NestedMeta, Path,
+    PathArguments, Type, TypePath, UnOp, Variant,
 };
 
+/// The largest field number protobuf accepts.
+pub const MAX_FIELD_NUMBER: i32 = 536_870_911;
+
+/// Field numbers reserved for the protobuf implementation itself.
+pub const RESERVED_FIELD_NUMBERS: RangeInclusive<i32> = 19_000..=19_999;
+
+/// Collections encoded as `repeated` fields, besides arrays.
+const REPEATED_COLLECTIONS: &[&str] =
+    &["Vec", "VecDeque", "LinkedList", "BinaryHeap", "HashSet", "BTreeSet", "IndexSet", "SmallVec"];
+
+/// Collections encoded as `map<K, V>` fields.
+const MAP_COLLECTIONS: &[&str] = &["HashMap", "BTreeMap", "IndexMap"];
+
+/// The kind of collection a field type is, which decides both its label in the schema and how
+/// the derived conversions walk its elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collection {
+    /// `[T; N]`, a `repeated` field with a fixed length.
+    Array,
+    /// `Vec<T>`, `HashSet<T>` and the like, a `repeated` field.
+    Repeated,
+    /// `HashMap<K, V>` and the like, a `map<K, V>` field.
+    Map,
+    /// `Option<T>`, an `optional` field.
+    Optional,
+}
+
+impl Collection {
+    /// Returns the kind of collection `ty` is. Byte buffers are not collections, as they are
+    /// encoded as a single `bytes` field.
+    pub fn of(ty: &Type) -> Option<Self> {
+        if is_bytes(ty) {
+            return None;
+        }
+        match ty {
+            Type::Array(_) => Some(Self::Array),
+            Type::Path(type_path) => {
+                let ident = type_path.path.segments.last()?.ident.to_string();
+                if REPEATED_COLLECTIONS.contains(&ident.as_str()) {
+                    Some(Self::Repeated)
+                } else if MAP_COLLECTIONS.contains(&ident.as_str()) {
+                    Some(Self::Map)
+                } else if ident == "Option" {
+                    Some(Self::Optional)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_repeated(self) -> bool {
+        matches!(self, Self::Array | Self::Repeated)
+    }
+
+    /// Returns the name of the only field of the message wrapping this kind of collection when
+    /// it is nested in another.
+    pub fn wrapper_field(self) -> &'static str {
+        match self {
+            Self::Array | Self::Repeated => "items",
+            Self::Map => "entries",
+            Self::Optional => "value",
+        }
+    }
+}
+
+/// Returns the name of the message wrapping a collection nested in another, e.g. `VecOfPerson`
+/// for the `Vec<Person>` of a `Vec<Vec<Person>>`. Collections of the same kind share wrappers.
+pub fn wrapper_name(ty: &Type) -> syn::Result<String> {
+    let element = || {
+        element_type(ty)
+            .ok_or_else(|| syn::Error::new_spanned(ty, "the element type cannot be named"))
+    };
+    match Collection::of(ty) {
+        Some(Collection::Array) | Some(Collection::Repeated) => {
+            Ok(format!("VecOf{}", type_name(element()?)?))
+        }
+        Some(Collection::Optional) => Ok(format!("OptionOf{}", type_name(element()?)?)),
+        Some(Collection::Map) => {
+            let (key, value) = map_types(ty).ok_or_else(|| {
+                syn::Error::new_spanned(ty, "the key and value types cannot be named")
+            })?;
+            Ok(format!("MapOf{}To{}", type_name(key)?, type_name(value)?))
+        }
+        None => Err(syn::Error::new_spanned(ty, "only collections are wrapped")),
+    }
+}
+
+/// Returns a name for `ty` to build wrapper names from, e.g. `PagePerson` for `Page<Person>`.
+fn type_name(ty: &Type) -> syn::Result<String> {
+    if is_bytes(ty) {
+        return Ok("Bytes".to_string());
+    }
+    if Collection::of(ty).is_some() {
+        return wrapper_name(ty);
+    }
+    if let Some(borrowed) = borrowed(ty) {
+        return type_name(borrowed);
+    }
+    let ident = type_ident(ty)?;
+    generic_types(ty)
+        .into_iter()
+        .try_fold(ident.to_string().to_camel_case(), |name, ty| Ok(name + &type_name(ty)?))
+}
+
+/// Paths of the `Bytes` type of the `bytes` crate.
+const BYTES_PATHS: &[&str] = &["bytes::Bytes", "protobuf_gen::bytes::Bytes"];
+
+/// Returns whether `ty` is a byte buffer encoded as a `bytes` field: `Vec<u8>`, `[u8; N]` or
+/// `bytes::Bytes` written in full. A single `Bytes` may also be an item of the user, see
+/// `is_bytes_path`.
+pub fn is_bytes(ty: &Type) -> bool {
+    let is_u8 = |ty: &Type| matches!(ty, Type::Path(type_path) if type_path.path.is_ident("u8"));
+    match ty {
+        Type::Array(type_array) => is_u8(&type_array.elem),
+        Type::Path(type_path) => match type_path.path.segments.last() {
+            Some(segment) if segment.ident == "Bytes" => {
+                let path: Vec<_> = type_path
+                    .path
+                    .segments
+                    .iter()
+                    .map(|segment| segment.ident.to_string())
+                    .collect();
+                is_bytes_path(&path)
+            }
+            Some(segment) if segment.ident == "Vec" => {
+                matches!(generic_types(ty)[..], [elem] if is_u8(elem))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns whether the type at `path` is `bytes::Bytes`.
+pub fn is_bytes_path(path: &[String]) -> bool {
+    BYTES_PATHS.iter().any(|bytes| bytes.split("::").eq(path.iter().map(String::as_str)))
+}
+
+/// Smart pointers encoded as the type they point to.
+const POINTERS: &[&str] = &["Box", "Rc", "Arc"];
+
+/// Returns the type a `Box<T>`, `Rc<T>` or `Arc<T>` points to.
+pub fn pointee(ty: &Type) -> Option<&Type> {
+    let ident = match ty {
+        Type::Path(type_path) => &type_path.path.segments.last()?.ident,
+        _ => return None,
+    };
+    if !POINTERS.iter().any(|x| ident == x) {
+        return None;
+    }
+    match generic_types(ty)[..] {
+        [pointee] => Some(pointee),
+        _ => None,
+    }
+}
+
+/// Returns the type a `Cow<'a, B>` or a `&'a B` borrows.
+pub fn borrowed(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Reference(type_reference) => Some(&type_reference.elem),
+        Type::Path(type_path) if type_path.path.segments.last()?.ident == "Cow" => {
+            generic_types(ty).first().copied()
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether `field` borrows a string or bytes, possibly in an `Option` or a `Vec`, which are
+/// encoded from where they are borrowed.
+pub fn borrows_bytes(field: &syn::Field) -> bool {
+    if syn_util::contains_attribute(&field.attrs, &["protobuf_gen", "opaque"]) {
+        return false;
+    }
+    let ty = match Collection::of(&field.ty) {
+        Some(Collection::Optional) => element_type(&field.ty),
+        Some(Collection::Repeated) if type_ident(&field.ty).is_ok_and(|ident| ident == "Vec") => {
+            element_type(&field.ty)
+        }
+        Some(_) => None,
+        None => Some(&field.ty),
+    };
+    match ty.and_then(borrowed) {
+        Some(Type::Path(type_path)) => type_path.path.is_ident("str"),
+        Some(Type::Slice(type_slice)) => {
+            matches!(&*type_slice.elem, Type::Path(type_path) if type_path.path.is_ident("u8"))
+        }
+        _ => false,
+    }
+}
+
+/// Returns the name of the constant the proxy of a message holds the number of `field` in, which
+/// the derived encoding writes borrowed strings and bytes with.
+pub fn field_tag_name(field: &str) -> String {
+    format!("{}_TAG", field.trim_start_matches("r#").to_uppercase())
+}
+
+/// Integers with `NonZero` counterparts in `std::num`.
+const NON_ZERO_INTEGERS: &[&str] =
+    &["u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize"];
+
+/// Returns the integer a `NonZeroU32`-style or `NonZero<u32>` type wraps, which it is encoded as.
+pub fn non_zero_base(ty: &Type) -> Option<Type> {
+    let ident = match ty {
+        Type::Path(type_path) => &type_path.path.segments.last()?.ident,
+        _ => return None,
+    };
+    if ident == "NonZero" {
+        return match generic_types(ty)[..] {
+            [base] => Some(base.clone()),
+            _ => None,
+        };
+    }
+    let base = ident.to_string().strip_prefix("NonZero")?.to_lowercase();
+    if NON_ZERO_INTEGERS.contains(&base.as_str()) {
+        syn::parse_str(&base).ok()
+    } else {
+        None
+    }
+}
+
+/// Well-known protobuf types by the paths of the Rust types generated as them, with the files
+/// defining them.
+const WELL_KNOWN_TYPES: &[(&str, &str, &str)] = &[
+    ("std::time::Duration", "google.protobuf.Duration", "google/protobuf/duration.proto"),
+    ("core::time::Duration", "google.protobuf.Duration", "google/protobuf/duration.proto"),
+    ("std::time::SystemTime", "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+    #[cfg(feature = "chrono")]
+    ("chrono::DateTime", "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+];
+
+/// Time zones of the `chrono::DateTime`s that can be generated as `google.protobuf.Timestamp`.
+pub const TIME_ZONES: &[&str] = &["Utc", "Local", "FixedOffset"];
+
+/// Returns whether `ty` is named like a well-known type, as in `Duration` or
+/// `std::time::Duration`. Whether it is one depends on what the name refers to, which only the
+/// `use` items and the items around it tell.
+pub fn has_well_known_name(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path.path.segments.last().is_some_and(|segment| {
+            WELL_KNOWN_TYPES
+                .iter()
+                .any(|(path, _, _)| path.ends_with(&format!("::{}", segment.ident)))
+        }),
+        _ => false,
+    }
+}
+
+/// Returns the well-known protobuf type the Rust type at `path` is generated as, and the file to
+/// import for it. A single name such as `Duration` stands for the first type of that name.
+pub fn well_known_type(path: &[String]) -> Option<(&'static str, &'static str)> {
+    WELL_KNOWN_TYPES
+        .iter()
+        .find(|(rust, _, _)| match path {
+            [name] => rust.ends_with(&format!("::{name}")),
+            _ => rust.split("::").eq(path.iter().map(String::as_str)),
+        })
+        .map(|(_, proto, file)| (*proto, *file))
+}
+
+/// Returns the type arguments of the last path segment, e.g. `K` and `V` of `HashMap<K, V>`.
+pub fn generic_types(ty: &Type) -> Vec<&Type> {
+    let arguments = match ty {
+        Type::Path(type_path) => type_path.path.segments.last().map(|x| &x.arguments),
+        _ => None,
+    };
+    match arguments {
+        Some(PathArguments::AngleBracketed(g)) => g
+            .args
+            .iter()
+            .filter_map(|x| match x {
+                GenericArgument::Type(g) => Some(g),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the element type of a repeated or optional collection. The inline storage of a
+/// `SmallVec<[T; N]>` is looked through.
+pub fn element_type(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Array(type_array) => Some(&type_array.elem),
+        Type::Path(type_path) if type_path.path.segments.last()?.ident == "SmallVec" => {
+            match generic_types(ty).first()? {
+                Type::Array(type_array) => Some(&type_array.elem),
+                elem => Some(elem),
+            }
+        }
+        _ => generic_types(ty).first().copied(),
+    }
+}
+
+/// Returns the key and value types of a map collection.
+pub fn map_types(ty: &Type) -> Option<(&Type, &Type)> {
+    match generic_types(ty)[..] {
+        [key, value, ..] => Some((key, value)),
+        _ => None,
+    }
+}
+
 pub trait Extract {
     fn extract_message_with_fields_named(&mut self, _: &ItemStruct, _: &FieldsNamed) {}
 
@@ -19,6 +329,10 @@ pub trait Extract {
     fn extract_one_of(&mut self, _: &ItemEnum) {}
 
     fn extract_enumerator(&mut self, _: &ItemEnum) {}
+
+    /// Called instead of the methods above for an item, or a variant of it, that cannot be
+    /// extracted.
+    fn extract_error(&mut self, _: &Ident, _: syn::Error) {}
 }
 
 pub fn extract_nested_message<T: Extract + ?Sized>(
@@ -33,12 +347,14 @@ pub fn extract_nested_message<T: Extract + ?Sized>(
         Fields::Unit => {
             e.extract_nested_message_with_fields_unit(item_enum, variant);
         }
-        _ => {
-            panic!(
-                "only unit and 'struct' with named fields can be converted to nested 'message': \"{:?}\"",
-                item_enum.ident
-            );
-        }
+        // a single-element tuple variant maps directly to its inner type.
+        Fields::Unnamed(fields_unnamed) if fields_unnamed.unnamed.len() == 1 => {}
+        Fields::Unnamed(fields_unnamed) => match name_tuple_fields(fields_unnamed) {
+            Ok(fields_named) => {
+                e.extract_nested_message_with_fields_named(item_enum, variant, &fields_named)
+            }
+            Err(error) => e.extract_error(&item_enum.ident, error),
+        },
     }
 }
 
@@ -49,21 +365,264 @@ pub fn extract_message<T: Extract + ?Sized>(e: &mut T, item_struct: &ItemStruct)
                 || syn_util::contains_attribute(&field.attrs, &["protobuf_gen", "expose"]))
     }
 
-    if let syn::Fields::Named(fields_named) = &item_struct.fields {
-        let fields_named = FieldsNamed {
+    // fields of tuple structs are converted regardless of their visibility, as newtypes rarely
+    // expose their inner value.
+    fn filter_tuple_field(field: &syn::Field) -> bool {
+        !syn_util::contains_attribute(&field.attrs, &["protobuf_gen", "skip"])
+    }
+
+    let fields_named = match &item_struct.fields {
+        Fields::Named(fields_named) => FieldsNamed {
             named: fields_named.named.iter().cloned().filter(filter_field).collect(),
             ..fields_named.clone()
+        },
+        Fields::Unnamed(fields_unnamed) => match name_tuple_fields(fields_unnamed) {
+            Ok(fields_named) => FieldsNamed {
+                named: fields_named.named.into_iter().filter(filter_tuple_field).collect(),
+                ..fields_named
+            },
+            Err(error) => return e.extract_error(&item_struct.ident, error),
+        },
+        Fields::Unit => FieldsNamed { brace_token: Default::default(), named: Default::default() },
+    };
+    // a struct without any fields to convert, like a unit struct, becomes an empty message.
+    e.extract_message_with_fields_named(item_struct, &fields_named);
+}
+
+/// Returns the name of the `index`-th field of a tuple struct, given by
+/// `#[protobuf_gen(name = "...")]` or `field_{index}` otherwise. Names like `_0` are avoided, since
+/// prost strips leading underscores and would generate a proxy field named `0`.
+pub fn tuple_field_ident(index: usize, field: &syn::Field) -> syn::Result<Ident> {
+    match syn_util::get_attribute_value::<String>(&field.attrs, &["protobuf_gen", "name"]) {
+        Some(name) => syn::parse_str(&name).map_err(|_| {
+            syn::Error::new_spanned(field, format!("invalid field name: \"{}\"", name))
+        }),
+        None => Ok(format_ident!("field_{}", index)),
+    }
+}
+
+/// Gives every field of a tuple struct its name from `tuple_field_ident`, so that it can be
+/// converted like a struct with named fields.
+pub fn name_tuple_fields(fields_unnamed: &FieldsUnnamed) -> syn::Result<FieldsNamed> {
+    Ok(FieldsNamed {
+        brace_token: Default::default(),
+        named: fields_unnamed
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(index, field)| {
+                Ok(syn::Field {
+                    ident: Some(tuple_field_ident(index, field)?),
+                    colon_token: Some(Default::default()),
+                    ..field.clone()
+                })
+            })
+            .collect::<syn::Result<_>>()?,
+    })
+}
+
+/// Protobuf scalar types an integer field can be encoded as.
+const INTEGER_ENCODINGS: &[&str] = &[
+    "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32", "fixed64", "sfixed32",
+    "sfixed64",
+];
+
+/// Returns the integer encoding given by `#[protobuf_gen(encoding = "fixed64")]`, if any.
+pub fn integer_encoding(attrs: &[Attribute]) -> Result<Option<String>, String> {
+    match syn_util::get_attribute_value::<String>(attrs, &["protobuf_gen", "encoding"]) {
+        Some(encoding) if INTEGER_ENCODINGS.contains(&encoding.as_str()) => Ok(Some(encoding)),
+        Some(encoding) => Err(format!(
+            "unknown encoding \"{}\": expected one of {}",
+            encoding,
+            INTEGER_ENCODINGS.join(", ")
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Returns the field number given by `#[protobuf_gen(tag = N)]`, if any.
+pub fn field_tag(attrs: &[Attribute]) -> Result<Option<i32>, String> {
+    let tag = match syn_util::get_attribute_value::<u64>(attrs, &["protobuf_gen", "tag"]) {
+        Some(tag) => tag,
+        None => return Ok(None),
+    };
+
+    match i32::try_from(tag) {
+        Ok(tag) if (1..=MAX_FIELD_NUMBER).contains(&tag) && !RESERVED_FIELD_NUMBERS.contains(&tag) => {
+            Ok(Some(tag))
+        }
+        _ => Err(format!(
+            "tag {tag} is out of range: field numbers must be in 1..={MAX_FIELD_NUMBER} and outside {}..={}",
+            RESERVED_FIELD_NUMBERS.start(),
+            RESERVED_FIELD_NUMBERS.end()
+        )),
+    }
+}
+
+/// Numbers fields by their explicit `tag` or, failing that, by their 1-based position, skipping
+/// the `reserved` numbers, those of the protobuf implementation and those taken by tags. Tagged
+/// fields still take up their positions, so `{ a, #[protobuf_gen(tag = 5)] b, c }` is numbered
+/// 1, 5 and 3, and `{ #[protobuf_gen(tag = 2)] a, b }` is numbered 2 and 3.
+pub fn field_numbers<'a, I>(fields: I, reserved: &[i32]) -> Result<Vec<i32>, String>
+where
+    I: IntoIterator<Item = (String, &'a [Attribute])>,
+{
+    let tags = fields
+        .into_iter()
+        .map(|(name, attrs)| {
+            let tag = field_tag(attrs).map_err(|e| format!("\"{name}\": {e}"))?;
+            Ok((name, tag))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let taken: Vec<_> = tags.iter().filter_map(|(_, tag)| *tag).collect();
+
+    let mut numbers = Vec::new();
+    let mut next = 1;
+    for (name, tag) in tags {
+        while reserved.contains(&next)
+            || RESERVED_FIELD_NUMBERS.contains(&next)
+            || taken.contains(&next)
+        {
+            next += 1;
+        }
+        numbers.push((name, tag.unwrap_or(next)));
+        next += 1;
+    }
+    check_field_numbers(&numbers)?;
+    Ok(numbers.into_iter().map(|(_, number)| number).collect())
+}
+
+/// Checks the explicit `tag`s of fields, which must be in range and given to one field only. The
+/// numbers of the other fields depend on the lock file, which only the generator reads.
+pub fn check_field_tags<'a, I>(fields: I) -> Result<(), String>
+where
+    I: IntoIterator<Item = (String, &'a [Attribute])>,
+{
+    let mut numbers = Vec::new();
+    for (name, attrs) in fields {
+        if let Some(tag) = field_tag(attrs).map_err(|e| format!("\"{name}\": {e}"))? {
+            numbers.push((name, tag));
+        }
+    }
+    check_field_numbers(&numbers)
+}
+
+/// Rejects two fields sharing a field number.
+pub fn check_field_numbers(numbers: &[(String, i32)]) -> Result<(), String> {
+    let mut names = HashMap::new();
+    for (name, number) in numbers {
+        if let Some(other) = names.insert(number, name) {
+            return Err(format!("\"{other}\" and \"{name}\" have the same field number {number}"));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the values of a C-like enum if any variant has an explicit discriminant or
+/// `#[protobuf_gen(value = N)]`, following Rust's rule that a variant without one takes the
+/// previous value plus one.
+pub fn enum_values(item_enum: &ItemEnum) -> Result<Option<Vec<i32>>, String> {
+    fn discriminant(expr: &Expr) -> Option<i32> {
+        match expr {
+            Expr::Lit(ExprLit { lit: Lit::Int(int), .. }) => int.base10_parse().ok(),
+            Expr::Unary(ExprUnary { op: UnOp::Neg(_), expr, .. }) => {
+                discriminant(expr)?.checked_neg()
+            }
+            Expr::Group(group) => discriminant(&group.expr),
+            Expr::Paren(paren) => discriminant(&paren.expr),
+            _ => None,
+        }
+    }
+
+    let mut explicit = false;
+    let mut values = Vec::new();
+    // `None` once a variant has the value `i32::MAX`, which no implicit value can follow.
+    let mut next = Some(0i32);
+    for variant in &item_enum.variants {
+        let value = match syn_util::get_attribute_value::<Lit>(
+            &variant.attrs,
+            &["protobuf_gen", "value"],
+        ) {
+            Some(Lit::Int(int)) => Some(int.base10_parse().map_err(|e| e.to_string())?),
+            Some(Lit::Str(s)) => Some(s.value().parse().map_err(|_| {
+                format!("invalid value of \"{}\": \"{}\"", variant.ident, s.value())
+            })?),
+            Some(_) => return Err(format!("invalid value of \"{}\"", variant.ident)),
+            None => match &variant.discriminant {
+                Some((_, expr)) => Some(discriminant(expr).ok_or_else(|| {
+                    format!("the discriminant of \"{}\" must be an i32 literal", variant.ident)
+                })?),
+                None => None,
+            },
         };
-        if !fields_named.named.is_empty() {
-            e.extract_message_with_fields_named(item_struct, &fields_named);
-            return;
+        explicit |= value.is_some();
+
+        let value = match value.or(next) {
+            Some(value) => value,
+            None => {
+                return Err(format!("the implicit value of \"{}\" overflows i32", variant.ident))
+            }
+        };
+        next = value.checked_add(1);
+        values.push((variant.ident.to_string(), value));
+    }
+
+    if !explicit {
+        return Ok(None);
+    }
+    if !values.iter().any(|(_, value)| *value == 0) {
+        return Err("proto3 requires one of the variants to have the value 0".to_string());
+    }
+    let mut names = HashMap::new();
+    for (name, value) in &values {
+        if let Some(other) = names.insert(value, name) {
+            return Err(format!("\"{other}\" and \"{name}\" have the same value {value}"));
         }
     }
+    Ok(Some(values.into_iter().map(|(_, value)| value).collect()))
+}
+
+/// Returns the name prost gives to `variant` of `enum_name`: the UpperCamelCase variant name
+/// without the enum name in front of it.
+pub fn proxy_variant_name(enum_name: &str, variant: &str) -> String {
+    let variant = variant.to_camel_case();
+    match variant.strip_prefix(&enum_name.to_camel_case()) {
+        Some(stripped) if stripped.starts_with(char::is_uppercase) => stripped.to_string(),
+        _ => variant,
+    }
+}
 
-    panic!(
-        "only 'struct' with named fields can be converted to 'message': \"{:?}\"",
-        item_struct.ident
-    );
+/// Returns the numbers and names listed in `#[protobuf_gen(reserved(3, 4, "old_name"))]`.
+pub fn reserved(attrs: &[Attribute]) -> Result<(Vec<i32>, Vec<String>), String> {
+    let mut numbers = Vec::new();
+    let mut names = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("protobuf_gen")) {
+        let nested = match attr.parse_meta() {
+            Ok(Meta::List(MetaList { nested, .. })) => nested,
+            _ => continue,
+        };
+        for nested_meta in nested {
+            let reserved = match nested_meta {
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("reserved") => list.nested,
+                _ => continue,
+            };
+            for item in reserved {
+                match item {
+                    NestedMeta::Lit(Lit::Int(number)) => {
+                        numbers.push(number.base10_parse().map_err(|e| e.to_string())?)
+                    }
+                    NestedMeta::Lit(Lit::Str(name)) => names.push(name.value()),
+                    _ => {
+                        return Err(
+                            "only integers and strings can be reserved: \"reserved(3, \"name\")\""
+                                .to_string(),
+                        )
+                    }
+                }
+            }
+        }
+    }
+    Ok((numbers, names))
 }
 
 fn path_to_string(path: &Path) -> String {
@@ -72,7 +631,8 @@ fn path_to_string(path: &Path) -> String {
     segments.join("::")
 }
 
-fn collect_items(file: &File) -> Vec<&Item> {
+/// Returns the structs and enums of `file` that derive `ProtobufGen`.
+pub fn collect_items(file: &File) -> Vec<&Item> {
     let is_protobuf_gen = |nested_meta: &NestedMeta| {
         if let NestedMeta::Meta(Meta::Path(path)) = nested_meta {
             path_to_string(&path) == "ProtobufGen"
@@ -86,7 +646,7 @@ fn collect_items(file: &File) -> Vec<&Item> {
         .filter(|item| match item {
             Item::Struct(ItemStruct { attrs, .. }) | Item::Enum(ItemEnum { attrs, .. }) => {
                 attrs.iter().any(|attr| {
-                    if let Meta::List(MetaList { path, nested, .. }) = attr.parse_meta().unwrap() {
+                    if let Ok(Meta::List(MetaList { path, nested, .. })) = attr.parse_meta() {
                         path_to_string(&path) == "derive"
                             && nested.iter().any(|nested_meta| is_protobuf_gen(nested_meta))
                     } else {
@@ -99,34 +659,180 @@ fn collect_items(file: &File) -> Vec<&Item> {
         .collect()
 }
 
-pub fn extract_from_file<T: Extract + ?Sized>(e: &mut T, file: &File) {
-    let items = collect_items(file);
+/// Returns the generic item instantiated by every `#[protobuf_gen(instantiate(Name = "Type"))]`
+/// on it, named `Name` and paired with the concrete `Type`. Items without type parameters are
+/// returned as they are.
+pub fn instantiate(item: &Item) -> syn::Result<Vec<(Item, Option<Type>)>> {
+    let (ident, attrs, generics) = match item {
+        Item::Struct(ItemStruct { ident, attrs, generics, .. })
+        | Item::Enum(ItemEnum { ident, attrs, generics, .. }) => (ident, attrs, generics),
+        _ => return Ok(vec![(item.clone(), None)]),
+    };
+    let params: Vec<_> = generics.type_params().map(|param| &param.ident).collect();
+    if params.is_empty() {
+        return Ok(vec![(item.clone(), None)]);
+    }
 
-    for item in items {
-        match item {
-            Item::Struct(item_struct) => {
-                extract_message(e, item_struct);
+    let instances = instances(attrs)?;
+    if instances.is_empty() {
+        return Err(syn::Error::new_spanned(
+            ident,
+            format!(
+                "generic `{}` needs instances: #[protobuf_gen(instantiate(Name = \"{}<...>\"))]",
+                ident, ident
+            ),
+        ));
+    }
+
+    instances
+        .into_iter()
+        .map(|(name, ty)| {
+            let args = generic_types(&ty);
+            if type_ident(&ty)? != ident || args.len() != params.len() {
+                return Err(syn::Error::new_spanned(
+                    &ty,
+                    format!("invalid instance of `{}`: `{}`", ident, ty.to_token_stream()),
+                ));
             }
-            Item::Enum(item_enum) => {
-                let is_unit = |v: &Variant| {
-                    if let Fields::Unit = v.fields {
-                        true
-                    } else {
-                        false
+
+            let mut substitution = Substitution(
+                params
+                    .iter()
+                    .map(|param| (*param).clone())
+                    .zip(args.into_iter().cloned())
+                    .collect(),
+            );
+            let mut instance = item.clone();
+            match &mut instance {
+                Item::Struct(ItemStruct { ident, generics, fields, .. }) => {
+                    *ident = name;
+                    *generics = Default::default();
+                    substitution.visit_fields_mut(fields);
+                }
+                Item::Enum(ItemEnum { ident, generics, variants, .. }) => {
+                    *ident = name;
+                    *generics = Default::default();
+                    for variant in variants {
+                        substitution.visit_fields_mut(&mut variant.fields);
                     }
-                };
+                }
+                _ => unreachable!(),
+            }
+            Ok((instance, Some(ty)))
+        })
+        .collect()
+}
+
+/// Parses `#[protobuf_gen(instantiate(PageOfPerson = "Page<Person>"))]`.
+pub fn instances(attrs: &[Attribute]) -> syn::Result<Vec<(Ident, Type)>> {
+    let mut instances = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("protobuf_gen")) {
+        let nested = match attr.parse_meta() {
+            Ok(Meta::List(MetaList { nested, .. })) => nested,
+            _ => continue,
+        };
+        for nested_meta in nested {
+            let instantiate = match nested_meta {
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("instantiate") => {
+                    list.nested
+                }
+                _ => continue,
+            };
+            for item in instantiate {
+                match item {
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                        path,
+                        lit: Lit::Str(ty),
+                        ..
+                    })) if path.get_ident().is_some() => {
+                        let parsed = ty.parse().map_err(|e| {
+                            syn::Error::new_spanned(&ty, format!("invalid instance type: {e}"))
+                        })?;
+                        instances.push((path.get_ident().unwrap().clone(), parsed));
+                    }
+                    item => {
+                        return Err(syn::Error::new_spanned(
+                            item,
+                            "instances must be named types: \"instantiate(PageOfPerson = \"Page<Person>\")\"",
+                        ))
+                    }
+                }
+            }
+        }
+    }
+    Ok(instances)
+}
+
+/// Returns the last identifier of a type path, e.g. `Page` of `crate::Page<Person>`.
+pub fn type_ident(ty: &Type) -> syn::Result<&Ident> {
+    match ty {
+        Type::Path(type_path) => match type_path.path.segments.last() {
+            Some(segment) => Ok(&segment.ident),
+            None => Err(syn::Error::new_spanned(ty, "expected a named type")),
+        },
+        _ => Err(syn::Error::new_spanned(ty, "expected a named type")),
+    }
+}
 
-                if item_enum.variants.iter().all(is_unit) {
-                    e.extract_enumerator(item_enum);
+/// Replaces type parameters with the types they are instantiated with.
+struct Substitution(HashMap<Ident, Type>);
+
+impl VisitMut for Substitution {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(TypePath { qself: None, path }) = ty {
+            if let Some(arg) = path.get_ident().and_then(|ident| self.0.get(ident)) {
+                *ty = arg.clone();
+                return;
+            }
+        }
+        visit_mut::visit_type_mut(self, ty);
+    }
+}
+
+pub fn extract_item<T: Extract + ?Sized>(e: &mut T, item: &Item) {
+    match item {
+        Item::Struct(item_struct) => {
+            extract_message(e, item_struct);
+        }
+        Item::Enum(item_enum) => {
+            let is_unit = |v: &Variant| {
+                if let Fields::Unit = v.fields {
+                    true
                 } else {
-                    e.extract_one_of(item_enum);
+                    false
+                }
+            };
 
-                    for variant in &item_enum.variants {
-                        extract_nested_message(e, item_enum, variant);
-                    }
+            if item_enum.variants.iter().all(is_unit) {
+                e.extract_enumerator(item_enum);
+            } else {
+                e.extract_one_of(item_enum);
+
+                for variant in &item_enum.variants {
+                    extract_nested_message(e, item_enum, variant);
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn extract_from_file<T: Extract + ?Sized>(e: &mut T, file: &File) {
+    let items = collect_items(file);
+
+    for item in items {
+        match instantiate(item) {
+            Ok(instances) => {
+                for (item, _) in instances {
+                    extract_item(e, &item);
                 }
             }
-            _ => unreachable!(),
+            Err(error) => match item {
+                Item::Struct(ItemStruct { ident, .. }) | Item::Enum(ItemEnum { ident, .. }) => {
+                    e.extract_error(ident, error)
+                }
+                _ => unreachable!(),
+            },
         }
     }
 }
@@ -0,0 +1,105 @@
+use std::borrow::Cow;
+
+use bytes::BufMut;
+use prost::encoding::{encode_key, encode_varint, encoded_len_varint, key_len, WireType};
+
+/// Fields borrowing strings or bytes, which the derived encoding writes from where they are
+/// borrowed instead of copying them into the proxy first.
+#[doc(hidden)]
+pub trait BorrowedField {
+    fn encoded_len(&self, tag: u32) -> usize;
+
+    fn encode<B: BufMut>(&self, tag: u32, buffer: &mut B);
+}
+
+/// The strings and bytes a `BorrowedField` can hold.
+#[doc(hidden)]
+pub trait BorrowedBytes {
+    fn borrowed_bytes(&self) -> &[u8];
+}
+
+impl BorrowedBytes for &str {
+    fn borrowed_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl BorrowedBytes for &[u8] {
+    fn borrowed_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl BorrowedBytes for Cow<'_, str> {
+    fn borrowed_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl BorrowedBytes for Cow<'_, [u8]> {
+    fn borrowed_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+fn value_len(tag: u32, value: &[u8]) -> usize {
+    key_len(tag) + encoded_len_varint(value.len() as u64) + value.len()
+}
+
+fn encode_value<B: BufMut>(tag: u32, value: &[u8], buffer: &mut B) {
+    encode_key(tag, WireType::LengthDelimited, buffer);
+    encode_varint(value.len() as u64, buffer);
+    buffer.put_slice(value);
+}
+
+/// Implements `BorrowedField` for a singular field, which proto3 leaves out when it is empty.
+macro_rules! singular_field {
+    ($($ty:ty),*) => {
+        $(
+            impl BorrowedField for $ty {
+                fn encoded_len(&self, tag: u32) -> usize {
+                    match self.borrowed_bytes() {
+                        [] => 0,
+                        value => value_len(tag, value),
+                    }
+                }
+
+                fn encode<B: BufMut>(&self, tag: u32, buffer: &mut B) {
+                    match self.borrowed_bytes() {
+                        [] => {}
+                        value => encode_value(tag, value, buffer),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+singular_field!(&str, &[u8], Cow<'_, str>, Cow<'_, [u8]>);
+
+impl<T: BorrowedBytes> BorrowedField for Option<T> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self.as_ref()
+            .map_or(0, |value| value_len(tag, value.borrowed_bytes()))
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buffer: &mut B) {
+        if let Some(value) = self {
+            encode_value(tag, value.borrowed_bytes(), buffer);
+        }
+    }
+}
+
+impl<T: BorrowedBytes> BorrowedField for Vec<T> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self.iter()
+            .map(|value| value_len(tag, value.borrowed_bytes()))
+            .sum()
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buffer: &mut B) {
+        for value in self {
+            encode_value(tag, value.borrowed_bytes(), buffer);
+        }
+    }
+}
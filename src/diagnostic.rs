@@ -0,0 +1,66 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use proc_macro2::Span;
+
+/// A construct in a source file that cannot be converted to protobuf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The source file, which is empty until `Config` fills it in.
+    pub path: PathBuf,
+    /// The line of the construct, starting from 1.
+    pub line: usize,
+    /// The column of the construct, starting from 1.
+    pub column: usize,
+    /// The struct or enum the construct is in.
+    pub item: String,
+    /// The field or variant the construct is in, if any.
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(error: &syn::Error, item: &str, field: Option<String>) -> Self {
+        let start = error.span().start();
+        Self {
+            path: PathBuf::new(),
+            line: start.line,
+            column: start.column + 1,
+            item: item.to_string(),
+            field,
+            message: error.to_string(),
+        }
+    }
+
+    /// Points at `span` in `item`, for errors of the item as a whole.
+    pub(crate) fn new_spanned(span: Span, item: &str, message: impl fmt::Display) -> Self {
+        Self::new(&syn::Error::new(span, message), item, None)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {} (in `{}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.message,
+            self.item
+        )?;
+        if let Some(field) = &self.field {
+            write!(f, "::{field}")?;
+        }
+        write!(f, "`)")
+    }
+}
+
+/// Lists `diagnostics` one per line.
+pub(crate) fn format_all(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(Diagnostic::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -13,8 +13,20 @@ pub enum Error {
     IoError(#[from] IoError),
     #[error("prost encode error: {0:?}")]
     ProstEncodeError(#[from] EncodeError),
+    #[error("insufficient buffer capacity (required: {required}, remaining: {remaining})")]
+    InsufficientBuffer { required: usize, remaining: usize },
     #[error("prost decode error: {0:?}")]
     ProstDecodeError(#[from] DecodeError),
+    #[error("`{field}` has a length of {actual} instead of {expected}")]
+    InvalidLength {
+        field: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("`{field}` is zero")]
+    ZeroValue { field: String },
+    #[error("`{field}` is not a valid char: {value:#x}")]
+    InvalidChar { field: String, value: u32 },
     #[error("failed to convert `{0}`")]
     TryFromError(
         String,
@@ -31,6 +43,34 @@ impl Error {
         Self::InvalidIdent(ident.to_string())
     }
 
+    pub fn new_insufficient_buffer(required: usize, remaining: usize) -> Self {
+        Self::InsufficientBuffer {
+            required,
+            remaining,
+        }
+    }
+
+    pub fn new_invalid_length<T: ToString>(field: T, expected: usize, actual: usize) -> Self {
+        Self::InvalidLength {
+            field: field.to_string(),
+            expected,
+            actual,
+        }
+    }
+
+    pub fn new_zero_value<T: ToString>(field: T) -> Self {
+        Self::ZeroValue {
+            field: field.to_string(),
+        }
+    }
+
+    pub fn new_invalid_char<T: ToString>(field: T, value: u32) -> Self {
+        Self::InvalidChar {
+            field: field.to_string(),
+            value,
+        }
+    }
+
     pub fn new_try_from_error<
         T: ToString,
         E: Into<Box<dyn ::std::error::Error + Sync + Send + 'static>>,
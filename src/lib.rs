@@ -2,28 +2,45 @@
 extern crate log;
 extern crate protobuf_gen_extract as extract;
 
+mod borrowed;
+pub mod diagnostic;
 pub mod error;
+mod lock;
+mod module;
 pub mod parse;
 pub mod print;
 mod types;
+mod well_known;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::{self, Write};
-use std::option_env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use quote::ToTokens;
 use syn::{Ident, ItemEnum, ItemStruct};
 use thiserror::Error;
 
-use crate::parse::SchemaFile;
+use crate::lock::FieldNumberLock;
+use crate::module::Scope;
+use crate::parse::{type_path_ident, SchemaFile};
 use crate::print::SchemaPrinter;
 use crate::types::FieldType;
+#[doc(hidden)]
+pub use borrowed::{BorrowedBytes, BorrowedField};
 pub use bytes;
+pub use diagnostic::Diagnostic;
 pub use error::Error;
 pub use protobuf_gen_derive::*;
+#[doc(hidden)]
+pub use well_known::WellKnownField;
+pub use well_known::WellKnownType;
 
+/// Encoding and decoding through the generated proxy, derived with `#[derive(ProtobufGen)]`.
+/// Items holding references such as `&'a str` cannot be decoded, so they do not implement it and
+/// are only encoded, with `Vec::<u8>::try_from`.
 pub trait ProtobufGen: Sized {
     type Error;
 
@@ -36,21 +53,98 @@ pub trait ProtobufGen: Sized {
     fn from_protobuf_length_delimited<B: bytes::Buf>(r: B) -> result::Result<Self, Self::Error>;
 }
 
+/// `Rc` and `Arc` fields, which are converted into proxies by value. The pointee is moved out of
+/// a pointer that is not shared, and cloned out of one that is, so it must be `Clone`.
+pub trait SharedPointer<T> {
+    fn into_pointee(self) -> T;
+}
+
+impl<T: Clone> SharedPointer<T> for std::rc::Rc<T> {
+    fn into_pointee(self) -> T {
+        std::rc::Rc::try_unwrap(self).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl<T: Clone> SharedPointer<T> for std::sync::Arc<T> {
+    fn into_pointee(self) -> T {
+        std::sync::Arc::try_unwrap(self).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
 pub struct Config {
     pub proto_target_dir: PathBuf,
     pub proxy_target_dir: Option<PathBuf>,
-    pub sources: HashMap<String, Vec<PathBuf>>,
-    pub type_replacement: HashMap<String, String>,
+    pub sources: BTreeMap<String, Vec<PathBuf>>,
+    /// Sources whose modules are followed, see `add_source_recursive`.
+    recursive_sources: HashSet<PathBuf>,
+    /// The root files of crates and the package prefixes of their modules, see `add_crate`.
+    crates: Vec<(PathBuf, String)>,
+    pub type_replacement: BTreeMap<String, String>,
     btree_map_targets: Vec<String>,
-    additional_imports: HashMap<String, Vec<PathBuf>>,
+    additional_imports: BTreeMap<String, Vec<PathBuf>>,
+    prefix_enum_values: bool,
+    signed_encoding: Option<IntegerEncoding>,
+    unsigned_encoding: Option<UnsignedEncoding>,
+}
+
+/// How integer fields are encoded on the wire, unless a field picks its own scalar type with
+/// `#[protobuf_gen(encoding = "fixed64")]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerEncoding {
+    /// `int32`/`int64` and `uint32`/`uint64`, efficient for small non-negative values.
+    Varint,
+    /// `sint32`/`sint64`, efficient for small negative values too. Signed integers only.
+    ZigZag,
+    /// `sfixed32`/`sfixed64` and `fixed32`/`fixed64`, efficient for large values such as hashes.
+    Fixed,
+}
+
+impl IntegerEncoding {
+    fn field_type(self, signed: bool, wide: bool) -> FieldType {
+        match (self, signed, wide) {
+            (Self::Varint, true, false) => FieldType::Int32,
+            (Self::Varint, true, true) => FieldType::Int64,
+            (Self::Varint, false, false) => FieldType::Uint32,
+            (Self::Varint, false, true) => FieldType::Uint64,
+            (Self::ZigZag, _, false) => FieldType::Sint32,
+            (Self::ZigZag, _, true) => FieldType::Sint64,
+            (Self::Fixed, true, false) => FieldType::Sfixed32,
+            (Self::Fixed, true, true) => FieldType::Sfixed64,
+            (Self::Fixed, false, false) => FieldType::Fixed32,
+            (Self::Fixed, false, true) => FieldType::Fixed64,
+        }
+    }
+}
+
+/// How unsigned integer fields are encoded on the wire, which is `IntegerEncoding` without
+/// zigzag encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsignedEncoding {
+    /// `uint32`/`uint64`, efficient for small values.
+    Varint,
+    /// `fixed32`/`fixed64`, efficient for large values such as hashes.
+    Fixed,
+}
+
+impl From<UnsignedEncoding> for IntegerEncoding {
+    fn from(encoding: UnsignedEncoding) -> Self {
+        match encoding {
+            UnsignedEncoding::Varint => Self::Varint,
+            UnsignedEncoding::Fixed => Self::Fixed,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("failed to read a file.")]
     IoError(#[from] io::Error),
-    #[error("failed to parse a string.")]
-    ParseError(#[from] syn::Error),
+    #[error("failed to parse {}:{}: {1}", .0.display(), .1.span().start().line)]
+    ParseError(PathBuf, #[source] syn::Error),
+    #[error("unsupported constructs in the sources:\n{}", diagnostic::format_all(.0))]
+    Unsupported(Vec<Diagnostic>),
+    #[error("the package prefix of the crate at {} is empty", .0.display())]
+    EmptyPackagePrefix(PathBuf),
 }
 
 impl Config {
@@ -61,10 +155,15 @@ impl Config {
         Self {
             proto_target_dir: proto_target_dir.into(),
             proxy_target_dir: proxy_target_dir.map(|p| p.into()),
-            sources: HashMap::new(),
-            type_replacement: HashMap::new(),
+            sources: BTreeMap::new(),
+            recursive_sources: HashSet::new(),
+            crates: Vec::new(),
+            type_replacement: BTreeMap::new(),
             btree_map_targets: Vec::new(),
-            additional_imports: HashMap::new(),
+            additional_imports: BTreeMap::new(),
+            prefix_enum_values: false,
+            signed_encoding: None,
+            unsigned_encoding: None,
         }
     }
 
@@ -76,6 +175,25 @@ impl Config {
         self.type_replacement.insert(typ.into(), "bytes".into());
     }
 
+    /// Prints the values of every enum as `ENUM_NAME_VARIANT`, so that enums in the same package
+    /// can share variant names. `#[protobuf_gen(prefix_values)]` does the same for a single enum.
+    pub fn prefix_enum_values(&mut self) {
+        self.prefix_enum_values = true;
+    }
+
+    /// Encodes every signed integer field as `encoding`. By default `i32` and `i64` are zigzag
+    /// encoded, while `i8`, `i16` and `isize` are varints.
+    pub fn signed_encoding(&mut self, encoding: IntegerEncoding) {
+        self.signed_encoding = Some(encoding);
+    }
+
+    /// Encodes every unsigned integer field as `encoding`, varints by default.
+    pub fn unsigned_encoding(&mut self, encoding: UnsignedEncoding) {
+        self.unsigned_encoding = Some(encoding);
+    }
+
+    /// Adds `file` to `package`. The module path of `file`, by which the `use` items of other
+    /// files refer to its types, is guessed from where it is under `src`.
     pub fn add_source<P: Into<PathBuf>, S: Into<String>>(&mut self, file: P, package: S) {
         self.sources
             .entry(package.into())
@@ -83,7 +201,75 @@ impl Config {
             .push(file.into());
     }
 
-    fn create_proto_file<P: AsRef<str>>(&self, package: P) -> io::Result<(File, PathBuf)> {
+    /// Adds `file` like `add_source`, along with the inline modules it contains and the files of
+    /// the modules it declares with `mod name;`, which are found as `rustc` would.
+    pub fn add_source_recursive<P: Into<PathBuf>, S: Into<String>>(&mut self, file: P, package: S) {
+        let file = file.into();
+        self.recursive_sources.insert(file.clone());
+        self.add_source(file, package);
+    }
+
+    /// Adds every module of the crate whose root file is `root`, such as `src/lib.rs`, that
+    /// defines `ProtobufGen` types. Each module goes to the package named after its path, so that
+    /// the types of `crate::net::peer` end up in `package_prefix.net.peer`, and those of the root
+    /// module in `package_prefix` itself. Fails if `package_prefix` is empty, as the packages of
+    /// the modules would start with a dot.
+    pub fn add_crate<P: Into<PathBuf>, S: Into<String>>(
+        &mut self,
+        root: P,
+        package_prefix: S,
+    ) -> result::Result<(), ConfigError> {
+        let root = root.into();
+        let package_prefix = package_prefix.into();
+        if package_prefix.is_empty() {
+            return Err(ConfigError::EmptyPackagePrefix(root));
+        }
+        self.crates.push((root, package_prefix));
+        Ok(())
+    }
+
+    /// Parses the sources of every package, keeping the path of each file to report progress.
+    fn read_sources(&self) -> result::Result<BTreeMap<String, Vec<Source>>, ConfigError> {
+        let mut sources: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (package, paths) in &self.sources {
+            for path in paths {
+                let root = module::infer_module_path(path);
+                for module in module::read_source(path, self.recursive_sources.contains(path))? {
+                    sources.entry(package.clone()).or_default().push(Source {
+                        module: root.clone().map(|mut root| {
+                            root.extend(module.path);
+                            root
+                        }),
+                        path: module.source,
+                        file: module.file,
+                    });
+                }
+            }
+        }
+        for (root, package_prefix) in &self.crates {
+            for module in module::read_modules(root)? {
+                if extract::collect_items(&module.file).is_empty() {
+                    continue;
+                }
+                let package = std::iter::once(package_prefix.clone())
+                    .chain(module.path.iter().cloned())
+                    .collect::<Vec<_>>()
+                    .join(".");
+                sources.entry(package).or_default().push(Source {
+                    module: Some(
+                        std::iter::once("crate".to_string())
+                            .chain(module.path)
+                            .collect(),
+                    ),
+                    path: module.source,
+                    file: module.file,
+                });
+            }
+        }
+        Ok(sources)
+    }
+
+    fn proto_file_path<P: AsRef<str>>(&self, package: P) -> PathBuf {
         let package: Vec<_> = package.as_ref().split('.').collect();
         let (dir, file) = package.split_at(package.len() - 1);
 
@@ -92,24 +278,117 @@ impl Config {
 
         let mut file_path = dir_path.as_path().join(PathBuf::from(file[0].to_string()));
         file_path.set_extension("proto");
+        file_path
+    }
+
+    /// Returns the files added with `add_import` that are found in `proto_target_dir`, which
+    /// protoc reads along with the generated `in_files`. Others, such as the well-known types,
+    /// come with protoc.
+    fn imported_files(&self, in_files: &BTreeMap<PathBuf, String>) -> Vec<PathBuf> {
+        let mut files: Vec<_> = self
+            .additional_imports
+            .values()
+            .flatten()
+            .map(|path| self.proto_target_dir.join(path))
+            .filter(|path| path.is_file() && !in_files.contains_key(path))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Returns a fingerprint of everything the proxies are generated from, which is stored next
+    /// to them to tell whether they are up to date.
+    fn proxy_fingerprint(
+        &self,
+        in_files: &BTreeMap<PathBuf, String>,
+        imported_files: &[PathBuf],
+        bytes_fields: &[String],
+        field_tags: &BTreeMap<String, String>,
+    ) -> io::Result<String> {
+        let mut fingerprint = Fingerprint::new();
+        for (path, schema) in in_files {
+            fingerprint.add(path.to_string_lossy().as_bytes());
+            fingerprint.add(schema.as_bytes());
+        }
+        for path in imported_files {
+            fingerprint.add(path.to_string_lossy().as_bytes());
+            fingerprint.add(&fs::read(path)?);
+        }
+        let mut bytes_fields = bytes_fields.to_vec();
+        bytes_fields.sort();
+        for field in bytes_fields.iter().chain(&self.btree_map_targets) {
+            fingerprint.add(field.as_bytes());
+        }
+        for tags in field_tags.values() {
+            fingerprint.add(tags.as_bytes());
+        }
+        fingerprint.add(PROXY_TYPE_ATTRIBUTE.as_bytes());
+        Ok(format!("{:016x}\n", fingerprint.0))
+    }
 
-        fs::create_dir_all(dir_path)?;
-        Ok((File::create(file_path.as_path())?, file_path))
+    /// The lock file pinning field numbers, kept next to `proto_target_dir`.
+    pub fn lock_file(&self) -> PathBuf {
+        self.proto_target_dir.with_extension("lock")
     }
 
-    fn build_context(&self) -> result::Result<Context, ConfigError> {
-        let mut context = Context::default();
+    fn build_context(
+        &self,
+        sources: &BTreeMap<String, Vec<Source>>,
+    ) -> result::Result<Context, ConfigError> {
+        let mut context = Context {
+            lock: FieldNumberLock::load(self.lock_file())?,
+            prefix_enum_values: self.prefix_enum_values,
+            ..Context::default()
+        };
+        let integers = [
+            ("i8", true, false),
+            ("i16", true, false),
+            ("i32", true, false),
+            ("i64", true, true),
+            ("isize", true, true),
+            ("u8", false, false),
+            ("u16", false, false),
+            ("u32", false, false),
+            ("u64", false, true),
+            ("usize", false, true),
+        ];
+        for (typ, signed, wide) in integers {
+            let encoding = if signed {
+                self.signed_encoding
+            } else {
+                self.unsigned_encoding.map(Into::into)
+            };
+            if let Some(encoding) = encoding {
+                context
+                    .type_replacement
+                    .insert(typ.to_string(), encoding.field_type(signed, wide));
+            }
+        }
         for (old, new) in &self.type_replacement {
             context.add_type_replacement(old.to_string(), new.to_string());
         }
 
         // generate item dictionary
-        for (package, sources) in &self.sources {
+        let mut diagnostics = Vec::new();
+        for (package, sources) in sources {
             for source in sources {
-                let file: syn::File = syn::parse_str(&fs::read_to_string(source)?)?;
-                context.item_dictionary.collect(&file.items, package);
+                if let Err(errors) =
+                    context
+                        .item_dictionary
+                        .collect(&source.file, package, source.module.as_deref())
+                {
+                    diagnostics.extend(errors.into_iter().map(|diagnostic| Diagnostic {
+                        path: source.path.clone(),
+                        ..diagnostic
+                    }));
+                }
             }
         }
+        if !diagnostics.is_empty() {
+            return Err(ConfigError::Unsupported(diagnostics));
+        }
+        context.index_instances();
         Ok(context)
     }
 
@@ -125,11 +404,15 @@ impl Config {
     }
 
     pub fn generate(&self) -> result::Result<(), ConfigError> {
-        let mut in_files = Vec::new();
-        let mut context = self.build_context()?;
+        let mut in_files = BTreeMap::new();
+        let mut bytes_fields = Vec::new();
+        let mut field_tags = BTreeMap::new();
+        let mut diagnostics = Vec::new();
+        let sources = self.read_sources()?;
+        let mut context = self.build_context(&sources)?;
 
         // generate protobuf schemas from Rust
-        for (package, sources) in &self.sources {
+        for (package, sources) in &sources {
             context.current_package = package.clone();
 
             let mut schema_file = if let Some(imports) = self.additional_imports.get(package) {
@@ -140,89 +423,283 @@ impl Config {
 
             schema_file.package = package.clone();
             for source in sources {
-                eprintln!("processing {} in {}", source.display(), package);
-                let syn_file: syn::File = syn::parse_str(&fs::read_to_string(source)?)?;
-                schema_file.merge(&mut parse::build_schema_file(&context, &syn_file));
+                eprintln!("processing {} in {}", source.path.display(), package);
+                context.current_module = source.module.clone();
+                match parse::build_schema_file(&context, &source.file) {
+                    Ok(mut file) => schema_file.merge(&mut file),
+                    Err(errors) => {
+                        diagnostics.extend(errors.into_iter().map(|diagnostic| Diagnostic {
+                            path: source.path.clone(),
+                            ..diagnostic
+                        }))
+                    }
+                }
             }
-
-            let (mut file, file_path) = self.create_proto_file(package)?;
-            write!(file, "{}", SchemaPrinter(&schema_file))?;
-            file.sync_all()?;
-
-            // https://man7.org/linux/man-pages/man2/fdatasync.2.html
-            //
-            // > Calling fsync() does not necessarily ensure that the entry in the directory
-            // > containing the file has also reached disk. For that an explicit fsync() on a file
-            // > descriptor for the directory is also needed.
-            if let Some(dir) = file_path.parent() {
-                File::open(dir)?.sync_all()?;
+            // the remaining packages are still parsed, to report all errors at once.
+            if !diagnostics.is_empty() {
+                continue;
             }
 
-            in_files.push(file_path);
-        }
-
-        // http://localhost:8080/pipeline-syntax/globals#env, assuming a Jenkins controller is
-        // running on localhost:8080.
-        //
-        // > A set of environment variables are made available to all Jenkins projects, including
-        // > Pipelines. The following is a general list of variable names that are available.
-        // > ...
-        // > CI: Statically set to the string "true" to indicate a "continuous integration"
-        // > execution environment.
-        if option_env!("CI").filter(|&value| value == "true").is_some() {
-            // HACK: Puts the current thread to sleep for a momemnt between writing and reading
-            // `*.proto`. There appears to be a delay in Amazon EC2 until all in-memory data
-            // reaches the filesystem. See <https://github.com/furiosa-ai/npu-tools/issues/2766>.
-            //
-            // https://man7.org/linux/man-pages/man2/fdatasync.2.html
-            //
-            // > The fsync() implementations in older kernels and lesser used filesystems do not
-            // > know how to flush disk caches. In these cases disk caches need to be disabled
-            // > using hdparm(8) or sdparm(8) to guarantee safe operation.
-            std::thread::sleep(std::time::Duration::from_secs(2));
+            context.lock.record(&schema_file);
+            bytes_fields.extend(schema_file.bytes_fields().iter().cloned());
+            field_tags.insert(package.clone(), field_tags_of(&schema_file));
+
+            let file_path = self.proto_file_path(package);
+            in_files.insert(file_path, SchemaPrinter(&schema_file).to_string());
+        }
+        // nothing is written unless every package converts, so that the schemas stay consistent.
+        if !diagnostics.is_empty() {
+            return Err(ConfigError::Unsupported(diagnostics));
+        }
+        for (file_path, schema) in &in_files {
+            write_if_changed(file_path, schema)?;
+        }
+        context.lock.save(self.lock_file())?;
+
+        // the build script only needs to run again when a source, an imported schema or the lock
+        // file changes, or when an output is edited or deleted.
+        let imported_files = self.imported_files(&in_files);
+        for source in sources.values().flatten() {
+            println!("cargo:rerun-if-changed={}", source.path.display());
+        }
+        for path in imported_files.iter().chain(in_files.keys()) {
+            println!("cargo:rerun-if-changed={}", path.display());
         }
+        println!("cargo:rerun-if-changed={}", self.lock_file().display());
 
         // generate Rust bindings for protobuf
         if let Some(ref proxy_target_dir) = self.proxy_target_dir {
             fs::create_dir_all(proxy_target_dir)?;
+            for package in sources.keys() {
+                let proxy_path = proxy_target_dir.join(format!("{package}.rs"));
+                println!("cargo:rerun-if-changed={}", proxy_path.display());
+            }
+
+            // prost is skipped if neither the schemas nor the options changed since its last run,
+            // as rewriting the proxies would rebuild whatever includes them.
+            let fingerprint_path = proxy_target_dir.join(PROXY_FINGERPRINT);
+            let fingerprint =
+                self.proxy_fingerprint(&in_files, &imported_files, &bytes_fields, &field_tags)?;
+            let proxies_exist = sources
+                .keys()
+                .all(|package| proxy_target_dir.join(format!("{package}.rs")).exists());
+            if proxies_exist
+                && fs::read_to_string(&fingerprint_path).ok() == Some(fingerprint.clone())
+            {
+                return Ok(());
+            }
 
             let mut config = prost_build::Config::new();
-            config.type_attribute(".", "#[allow(clippy::large_enum_variant)]");
+            config.type_attribute(".", PROXY_TYPE_ATTRIBUTE);
             config.out_dir(proxy_target_dir);
             config.btree_map(&self.btree_map_targets);
+            config.bytes(&bytes_fields);
+            let in_files: Vec<_> = in_files.keys().collect();
             config.compile_protos(&in_files, &[PathBuf::from(&self.proto_target_dir)])?;
+            for (package, tags) in &field_tags {
+                let proxy_path = proxy_target_dir.join(format!("{package}.rs"));
+                // prost leaves proxies it would not change alone, which still hold the tags.
+                let mut proxy = fs::read_to_string(&proxy_path)?;
+                if let Some(appended) = proxy.find(FIELD_TAGS_MARKER) {
+                    proxy.truncate(appended);
+                }
+                if !tags.is_empty() {
+                    proxy.push_str(FIELD_TAGS_MARKER);
+                    proxy.push_str(tags);
+                }
+                write_if_changed(&proxy_path, &proxy)?;
+            }
+            write_if_changed(&fingerprint_path, &fingerprint)?;
         }
         Ok(())
     }
 }
 
+/// Renders the numbers of the fields borrowing strings or bytes in the messages of `schema_file`
+/// as constants of their proxies, which the derived encoding writes them with.
+fn field_tags_of(schema_file: &SchemaFile) -> String {
+    let mut tags = String::new();
+    for (message, fields) in schema_file.borrowed_fields() {
+        tags.push_str(&format!(
+            "\n/// Numbers of the borrowed fields, which `ProtobufGen` writes from where they are borrowed.\n\
+             impl {message} {{\n"
+        ));
+        for (field, number) in fields {
+            let name = extract::field_tag_name(field);
+            tags.push_str(&format!("    pub const {name}: u32 = {number};\n"));
+        }
+        tags.push_str("}\n");
+    }
+    tags
+}
+
+/// Writes `contents` to `path` unless it already holds them, so that its modification time only
+/// changes along with its contents.
+///
+/// The contents are written to a temporary file next to `path` first, which is then renamed over
+/// it. Readers such as `protoc` thus see either the old or the new file, never a partial one, even
+/// if the build is interrupted. The name of the temporary file is unique to the call, so that
+/// threads writing the same file do not write into each other's.
+pub(crate) fn write_if_changed(path: &Path, contents: &str) -> io::Result<()> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(());
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    let call = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    temp_name.push(format!(".{}.{call}.tmp", std::process::id()));
+    let temp_path = dir.join(temp_name);
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|()| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+
+    // https://man7.org/linux/man-pages/man2/fdatasync.2.html
+    //
+    // > Calling fsync() does not necessarily ensure that the entry in the directory
+    // > containing the file has also reached disk. For that an explicit fsync() on a file
+    // > descriptor for the directory is also needed.
+    File::open(dir)?.sync_all()
+}
+
+/// Counts the temporary files of `write_if_changed`, to tell those of a process apart.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A 64-bit FNV-1a hash, which unlike `DefaultHasher` is the same with every Rust release, so
+/// that a new compiler does not run prost again.
+struct Fingerprint(u64);
+
+impl Fingerprint {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Adds `bytes` after their length, which keeps consecutive inputs apart.
+    fn add(&mut self, bytes: &[u8]) {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Attributes prost puts on every proxy type.
+const PROXY_TYPE_ATTRIBUTE: &str = "#[allow(clippy::large_enum_variant)]";
+
+/// The file in `proxy_target_dir` holding the fingerprint of what the proxies are generated from.
+const PROXY_FINGERPRINT: &str = ".protobuf-gen-fingerprint";
+
+/// Starts the field numbers appended to a proxy by `field_tags_of`. Whatever follows it was
+/// appended, so it is replaced rather than appended to again.
+const FIELD_TAGS_MARKER: &str = "\n// Numbers of borrowed fields, appended by protobuf-gen.\n";
+
+/// A source file, or one of its modules, to generate a package from.
+struct Source {
+    path: PathBuf,
+    /// The module path of the items, such as `crate::net::peer`, if known.
+    module: Option<Vec<String>>,
+    file: syn::File,
+}
+
+/// Where an item is defined.
+#[derive(Debug)]
+struct Definition {
+    name: String,
+    package: String,
+    module: Option<Vec<String>>,
+}
+
+/// An instance of a generic item, as in `#[protobuf_gen(instantiate(PageOfPerson = "Page<Person>"))]`.
+#[derive(Debug)]
+struct Instance {
+    name: Ident,
+    ty: syn::Type,
+    package: String,
+    /// The scope of the item, which the type arguments of `ty` are resolved in.
+    scope: Scope,
+}
+
 #[derive(Debug, Default)]
 pub struct ItemDictionary {
-    package_map: HashMap<String, Vec<String>>,
+    definitions: HashMap<String, Vec<Definition>>, // by the names of items
+    instances: HashMap<String, Ident>,             // by `Context::instance_key`
+    unindexed_instances: Vec<Instance>,
 }
 
 impl ItemDictionary {
-    pub fn collect(&mut self, items: &[syn::Item], package: &str) {
-        for item in items {
+    /// Adds the items of a source, or returns the diagnostics of the instances it cannot parse.
+    /// The paths of the diagnostics are left empty, as in `parse::build_schema_file`.
+    pub fn collect(
+        &mut self,
+        file: &syn::File,
+        package: &str,
+        module: Option<&[String]>,
+    ) -> result::Result<(), Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        for item in &file.items {
             match item {
-                syn::Item::Struct(ItemStruct { ident, .. })
-                | syn::Item::Enum(ItemEnum { ident, .. }) => {
-                    self.package_map
-                        .entry(ident.to_string())
-                        .or_default()
-                        .push(package.to_string());
+                syn::Item::Struct(ItemStruct { ident, attrs, .. })
+                | syn::Item::Enum(ItemEnum { ident, attrs, .. }) => {
+                    let instances = match extract::instances(attrs) {
+                        Ok(instances) => instances,
+                        Err(error) => {
+                            diagnostics.push(Diagnostic::new(&error, &ident.to_string(), None));
+                            continue;
+                        }
+                    };
+                    let names = if instances.is_empty() {
+                        vec![ident.clone()]
+                    } else {
+                        instances.iter().map(|(name, _)| name.clone()).collect()
+                    };
+                    for name in names {
+                        self.definitions
+                            .entry(name.to_string())
+                            .or_default()
+                            .push(Definition {
+                                name: name.to_string(),
+                                package: package.to_string(),
+                                module: module.map(<[String]>::to_vec),
+                            });
+                    }
+                    for (name, ty) in instances {
+                        self.unindexed_instances.push(Instance {
+                            name,
+                            ty,
+                            package: package.to_string(),
+                            scope: Scope::new(module.map(<[String]>::to_vec), file),
+                        });
+                    }
                 }
                 _ => {}
             }
         }
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub struct Context {
     current_package: String,
+    /// The module path of the source being parsed, if known.
+    current_module: Option<Vec<String>>,
     type_replacement: HashMap<String, FieldType>,
     item_dictionary: ItemDictionary,
+    lock: FieldNumberLock,
+    prefix_enum_values: bool,
 }
 
 impl Default for Context {
@@ -233,22 +710,32 @@ impl Default for Context {
                 ("f32".to_string(), FieldType::Float),
                 ("i8".to_string(), FieldType::Int32),
                 ("i16".to_string(), FieldType::Int32),
-                ("i32".to_string(), FieldType::Int32),
-                ("i64".to_string(), FieldType::Int64),
+                // `i32` and `i64` have always been zigzag encoded, so they stay so for
+                // compatibility with existing schemas.
+                ("i32".to_string(), FieldType::Sint32),
+                ("i64".to_string(), FieldType::Sint64),
                 ("u8".to_string(), FieldType::Uint32),
                 ("u16".to_string(), FieldType::Uint32),
                 ("u32".to_string(), FieldType::Uint32),
                 ("u64".to_string(), FieldType::Uint64),
                 ("usize".to_string(), FieldType::Uint64),
                 ("isize".to_string(), FieldType::Int64),
-                ("i32".to_string(), FieldType::Sint32),
-                ("i64".to_string(), FieldType::Sint64),
+                // 128-bit integers have no protobuf counterpart, so they are their 16 bytes in
+                // little-endian order.
+                ("u128".to_string(), FieldType::Bytes_),
+                ("i128".to_string(), FieldType::Bytes_),
+                // characters are their Unicode scalar values.
+                ("char".to_string(), FieldType::Uint32),
+                ("bool".to_string(), FieldType::Bool),
                 ("String".to_string(), FieldType::String_),
             ]
             .into_iter()
             .collect(),
             current_package: Default::default(),
+            current_module: None,
             item_dictionary: Default::default(),
+            lock: Default::default(),
+            prefix_enum_values: false,
         }
     }
 }
@@ -259,18 +746,282 @@ impl Context {
             .insert(old, FieldType::MessageOrEnum(new));
     }
 
-    pub fn get_package(&self, ident: &Ident) -> Option<&String> {
-        let packages = self.item_dictionary.package_map.get(&ident.to_string())?;
-        if packages.contains(&self.current_package) {
+    /// Returns the message an instantiated generic type such as `Page<Person>` is generated as.
+    pub(crate) fn get_instance(&self, scope: &Scope, ty: &syn::Type) -> Option<&Ident> {
+        let key = self.instance_key(scope, ty)?;
+        self.item_dictionary.instances.get(&key)
+    }
+
+    /// Indexes the collected instances by their keys, which can only be told once every item is
+    /// known. Type arguments are resolved in the package of the generic item.
+    fn index_instances(&mut self) {
+        let current_package = self.current_package.clone();
+        for instance in std::mem::take(&mut self.item_dictionary.unindexed_instances) {
+            self.current_package = instance.package;
+            if let Some(key) = self.instance_key(&instance.scope, &instance.ty) {
+                self.item_dictionary.instances.insert(key, instance.name);
+            }
+        }
+        self.current_package = current_package;
+    }
+
+    /// Identifies a generic type such as `Page<crate::people::Person>` by the name of the generic
+    /// item and the items its type arguments resolve to in `scope`, so that instances match
+    /// however their arguments are written. Returns `None` for types without type arguments.
+    fn instance_key(&self, scope: &Scope, ty: &syn::Type) -> Option<String> {
+        let syn::Type::Path(type_path) = ty else {
+            return None;
+        };
+        let segment = type_path.path.segments.last()?;
+        let syn::PathArguments::AngleBracketed(arguments) = &segment.arguments else {
             return None;
+        };
+        let arguments: Vec<_> = arguments
+            .args
+            .iter()
+            .map(|argument| match argument {
+                syn::GenericArgument::Type(ty) => self.argument_key(scope, ty),
+                _ => argument.to_token_stream().to_string(),
+            })
+            .collect();
+        Some(format!("{}<{}>", segment.ident, arguments.join(", ")))
+    }
+
+    /// Identifies a type argument by the package and the name of the item it resolves to, or by
+    /// its name if it is not an item, such as `u32`.
+    fn argument_key(&self, scope: &Scope, ty: &syn::Type) -> String {
+        if let Some(key) = self.instance_key(scope, ty) {
+            return key;
+        }
+        let syn::Type::Path(type_path) = ty else {
+            return ty.to_token_stream().to_string();
+        };
+        match self.resolve(scope, &type_path.path, None) {
+            Ok(Some(definition)) => format!("{}.{}", definition.package, definition.name),
+            _ => type_path_ident(type_path).to_string(),
         }
+    }
+
+    /// Returns whether an item named `name` is defined in the current package.
+    pub fn defines(&self, name: &str) -> bool {
+        self.item_dictionary
+            .definitions
+            .get(name)
+            .is_some_and(|definitions| {
+                definitions
+                    .iter()
+                    .any(|definition| definition.package == self.current_package)
+            })
+    }
+
+    /// Returns whether an item is defined at the absolute `path`, such as `crate::net::Config`.
+    fn defines_at(&self, path: &[String]) -> bool {
+        let Some((name, module)) = path.split_last() else {
+            return false;
+        };
+        self.item_dictionary
+            .definitions
+            .get(name)
+            .is_some_and(|definitions| {
+                definitions
+                    .iter()
+                    .any(|definition| definition.module.as_deref() == Some(module))
+            })
+    }
 
-        fn length_of_common_prefix(a: &str, b: &str) -> usize {
-            a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count()
+    /// Returns the item `path` refers to in `scope`, or `instance` if `path` is an instantiated
+    /// generic type.
+    ///
+    /// The item is looked up by the module path `path` resolves to, first relative to the current
+    /// module and then as it is, then among the items of the current module and the modules it
+    /// glob-imports, and then among the items of the current package. Fails if several items
+    /// could be meant.
+    fn resolve(
+        &self,
+        scope: &Scope,
+        path: &syn::Path,
+        instance: Option<&Ident>,
+    ) -> syn::Result<Option<&Definition>> {
+        let resolved = scope.resolve(path);
+        let name = match (instance, &resolved) {
+            (Some(instance), _) => instance.to_string(),
+            (None, Some(resolved)) => resolved.last().cloned().unwrap_or_default(),
+            (None, None) => path
+                .segments
+                .last()
+                .map(|segment| segment.ident.to_string())
+                .unwrap_or_default(),
+        };
+        let Some(definitions) = self.item_dictionary.definitions.get(&name) else {
+            return Ok(None);
+        };
+        let find = |module: &[String]| {
+            let mut found = definitions
+                .iter()
+                .filter(|definition| definition.module.as_deref() == Some(module));
+            found.next().filter(|_| found.next().is_none())
+        };
+        let in_current_package = || {
+            let mut found = definitions
+                .iter()
+                .filter(|definition| definition.package == self.current_package);
+            found.next().filter(|_| found.next().is_none())
+        };
+
+        let definition = match resolved {
+            Some(resolved) => scope
+                .relative(&resolved)
+                .and_then(|relative| find(&relative[..relative.len() - 1]))
+                .or_else(|| find(&resolved[..resolved.len() - 1])),
+            None => scope
+                .module()
+                .and_then(find)
+                .or_else(|| scope.globs().iter().find_map(|glob| find(glob)))
+                .or_else(in_current_package),
+        };
+        match definition {
+            Some(definition) => Ok(Some(definition)),
+            None if definitions.len() == 1 => Ok(Some(&definitions[0])),
+            None => Err(syn::Error::new_spanned(
+                path,
+                format!(
+                    "ambiguous type `{}`: `{name}` is defined in packages {}; import it with \
+                     `use` or give its full path",
+                    path.to_token_stream(),
+                    definitions
+                        .iter()
+                        .map(|definition| format!("\"{}\"", definition.package))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            )),
         }
+    }
 
-        packages
-            .iter()
-            .max_by_key(|package| length_of_common_prefix(package, &self.current_package))
+    /// Returns the package of the item `path` refers to, unless it is the current package.
+    /// Ambiguous paths have none, as they are reported along with the fields they are in.
+    fn get_package(
+        &self,
+        scope: &Scope,
+        path: &syn::Path,
+        instance: Option<&Ident>,
+    ) -> Option<&String> {
+        self.resolve(scope, path, instance)
+            .ok()
+            .flatten()
+            .map(|definition| &definition.package)
+            .filter(|&package| package != &self.current_package)
+    }
+
+    /// Returns the name of the message or enum `path` refers to, qualified with its package
+    /// unless it is the current package.
+    fn get_type_name(
+        &self,
+        scope: &Scope,
+        path: &syn::Path,
+        instance: Option<&Ident>,
+    ) -> syn::Result<Option<String>> {
+        let Some(definition) = self.resolve(scope, path, instance)? else {
+            return Ok(None);
+        };
+        Ok(Some(if definition.package == self.current_package {
+            definition.name.clone()
+        } else {
+            format!("{}.{}", definition.package, definition.name)
+        }))
+    }
+
+    /// Returns the well-known protobuf type `ty` is generated as, and the file to import for it.
+    ///
+    /// A type named like one is looked up by the path it resolves to in `scope`. Unless imported,
+    /// a single name such as `Duration` is a well-known type only if no item of that name is in
+    /// scope. Paths into other crates that are not well-known types cannot be converted.
+    fn well_known_type(
+        &self,
+        scope: &Scope,
+        ty: &syn::Type,
+    ) -> syn::Result<Option<(&'static str, &'static str)>> {
+        let syn::Type::Path(type_path) = ty else {
+            return Ok(None);
+        };
+        if !extract::has_well_known_name(ty) {
+            return Ok(None);
+        }
+        let path = match scope.resolve(&type_path.path) {
+            Some(path)
+                if scope
+                    .relative(&path)
+                    .is_some_and(|relative| self.defines_at(&relative)) =>
+            {
+                return Ok(None)
+            }
+            Some(path) => path,
+            None if self.resolve(scope, &type_path.path, None)?.is_some() => return Ok(None),
+            None => vec![extract::type_ident(ty)?.to_string()],
+        };
+        let Some(well_known) = extract::well_known_type(&path) else {
+            return match path.first().map(String::as_str) {
+                Some("crate") => Ok(None),
+                _ => Err(syn::Error::new_spanned(
+                    ty,
+                    format!(
+                        "`{}` is not a well-known type, and only items of this crate can be \
+                         converted",
+                        path.join("::")
+                    ),
+                )),
+            };
+        };
+        let time_zone =
+            extract::generic_types(ty).first().map(|time_zone| {
+                match extract::type_ident(time_zone) {
+                    Ok(ident) => ident.to_string(),
+                    Err(_) => time_zone.to_token_stream().to_string(),
+                }
+            });
+        match time_zone {
+            Some(time_zone) if !extract::TIME_ZONES.contains(&time_zone.as_str()) => {
+                Err(syn::Error::new_spanned(
+                    ty,
+                    format!(
+                        "time zone `{time_zone}` cannot be converted, only {}",
+                        extract::TIME_ZONES
+                            .iter()
+                            .map(|time_zone| format!("`{time_zone}`"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ))
+            }
+            _ => Ok(Some(well_known)),
+        }
+    }
+
+    /// Returns whether `ty` is a byte buffer encoded as a `bytes` field. Unless imported, a
+    /// single `Bytes` is `bytes::Bytes` only if no item of that name is in scope.
+    fn is_bytes(&self, scope: &Scope, ty: &syn::Type) -> bool {
+        if extract::is_bytes(ty) {
+            return true;
+        }
+        match ty {
+            syn::Type::Path(type_path)
+                if extract::type_ident(ty).is_ok_and(|ident| ident == "Bytes") =>
+            {
+                match scope.resolve(&type_path.path) {
+                    Some(path) => {
+                        extract::is_bytes_path(&path)
+                            && !scope
+                                .relative(&path)
+                                .is_some_and(|relative| self.defines_at(&relative))
+                    }
+                    None => matches!(self.resolve(scope, &type_path.path, None), Ok(None)),
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn current_module(&self) -> Option<Vec<String>> {
+        self.current_module.clone()
     }
 }
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::types::{Enumerator, FileDescriptor, Message};
+
+const HEADER: &str = "# Generated by protobuf-gen. Commit this file to keep field numbers stable.";
+
+/// Field numbers of every message field, oneof case and enum value ever generated, keyed by
+/// the fully qualified name of the enclosing message or enum.
+///
+/// Entries are never dropped when a field disappears from the source, so its number is not
+/// handed out again.
+#[derive(Debug, Default)]
+pub struct FieldNumberLock {
+    scopes: BTreeMap<String, BTreeMap<String, i32>>,
+}
+
+impl FieldNumberLock {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut lock = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid =
+                || io::Error::new(ErrorKind::InvalidData, format!("invalid line: {line}"));
+            let (path, number) = line.split_once('=').ok_or_else(invalid)?;
+            let (scope, name) = path.trim().rsplit_once('.').ok_or_else(invalid)?;
+            let number = number.trim().parse().map_err(|_| invalid())?;
+            lock.scopes
+                .entry(scope.to_string())
+                .or_default()
+                .insert(name.to_string(), number);
+        }
+        Ok(lock)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut content = format!("{HEADER}\n");
+        for (scope, numbers) in &self.scopes {
+            for (name, number) in numbers {
+                content.push_str(&format!("{scope}.{name} = {number}\n"));
+            }
+        }
+        // an unchanged lock is not rewritten, to keep its modification time.
+        crate::write_if_changed(path.as_ref(), &content)
+    }
+
+    /// Returns the numbers recorded for the message or enum `scope`, if it was seen before.
+    pub fn get(&self, scope: &str) -> Option<&BTreeMap<String, i32>> {
+        self.scopes.get(scope)
+    }
+
+    /// Records the numbers used in `file`, keeping entries of fields that no longer exist unless
+    /// their number has been taken over by another field.
+    pub fn record(&mut self, file: &FileDescriptor) {
+        for message in &file.messages {
+            self.record_message(&file.package, message);
+        }
+        for e in &file.enums {
+            self.record_enum(&file.package, e);
+        }
+    }
+
+    fn record_message(&mut self, parent: &str, message: &Message) {
+        let scope = format!("{parent}.{}", message.name);
+        let numbers = message
+            .fields
+            .iter()
+            .chain(message.oneofs.iter().flat_map(|oneof| &oneof.fields))
+            .map(|field| (field.name.clone(), field.number));
+        self.record_scope(&scope, numbers);
+
+        for nested in &message.messages {
+            self.record_message(&scope, nested);
+        }
+        for e in &message.enums {
+            self.record_enum(&scope, e);
+        }
+    }
+
+    fn record_enum(&mut self, parent: &str, e: &Enumerator) {
+        let scope = format!("{parent}.{}", e.name);
+        self.record_scope(&scope, e.fields.iter().cloned());
+    }
+
+    fn record_scope(&mut self, scope: &str, numbers: impl Iterator<Item = (String, i32)>) {
+        let current: BTreeMap<_, _> = numbers.collect();
+        let recorded = self.scopes.entry(scope.to_string()).or_default();
+        recorded.retain(|name, number| {
+            current.contains_key(name) || !current.values().any(|n| n == number)
+        });
+        recorded.extend(current);
+    }
+}
@@ -0,0 +1,313 @@
+//! Reads source files along with the modules they declare, following Rust's module file rules.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use syn::ext::IdentExt;
+use syn::{Attribute, Item, ItemMod, Lit, Meta, UseTree};
+
+use crate::ConfigError;
+
+/// A module of a source file, holding its items apart from its submodules.
+pub(crate) struct Module {
+    /// The names of the modules leading to this one from the root file, which has none.
+    pub(crate) path: Vec<String>,
+    /// The file defining the module, which is that of its parent for inline modules.
+    pub(crate) source: PathBuf,
+    pub(crate) file: syn::File,
+}
+
+/// Reads the file at `path` as a single module, or with `recursive`, along with every module it
+/// declares too.
+pub(crate) fn read_source(path: &Path, recursive: bool) -> Result<Vec<Module>, ConfigError> {
+    if recursive {
+        return read_modules(path);
+    }
+    Ok(vec![Module {
+        path: Vec::new(),
+        source: path.to_path_buf(),
+        file: parse_file(path)?,
+    }])
+}
+
+/// Guesses the module path of the file at `path` from where it is under `src`, as in
+/// `crate::net::peer` for `src/net/peer.rs`.
+pub(crate) fn infer_module_path(path: &Path) -> Option<Vec<String>> {
+    let components: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<_>>()?;
+    let src = components
+        .iter()
+        .rposition(|&component| component == "src")?;
+    let mut module_path = vec!["crate".to_string()];
+    module_path.extend(
+        components[src + 1..]
+            .iter()
+            .map(|component| component.to_string()),
+    );
+    let file = module_path.pop()?;
+    match file.strip_suffix(".rs")? {
+        "lib" | "main" | "mod" => {}
+        stem => module_path.push(stem.to_string()),
+    }
+    Some(module_path)
+}
+
+/// Reads the file at `root` and every module it declares, starting with the root module itself.
+pub(crate) fn read_modules(root: &Path) -> Result<Vec<Module>, ConfigError> {
+    let mut modules = Vec::new();
+    add_module_file(root, Vec::new(), &mut modules)?;
+    Ok(modules)
+}
+
+fn add_module_file(
+    source: &Path,
+    path: Vec<String>,
+    modules: &mut Vec<Module>,
+) -> Result<(), ConfigError> {
+    let file = parse_file(source)?;
+    let parent = source.parent().unwrap_or_else(|| Path::new(""));
+    add_module(file, source, path, &module_dir(source), parent, modules)
+}
+
+fn parse_file(path: &Path) -> Result<syn::File, ConfigError> {
+    syn::parse_str(&fs::read_to_string(path)?)
+        .map_err(|error| ConfigError::ParseError(path.to_path_buf(), error))
+}
+
+/// Returns the directory where the modules declared in the file at `path` live: next to a
+/// `lib.rs`, `main.rs` or `mod.rs`, and in a directory named after any other file.
+fn module_dir(path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some("lib" | "main" | "mod") | None => parent.to_path_buf(),
+        Some(stem) => parent.join(stem),
+    }
+}
+
+/// Adds the module at `path` made of `file` to `modules`, followed by its submodules. `dir` is
+/// where the files of submodules live, and `path_dir` is what `#[path = "..."]` attributes are
+/// relative to.
+fn add_module(
+    mut file: syn::File,
+    source: &Path,
+    path: Vec<String>,
+    dir: &Path,
+    path_dir: &Path,
+    modules: &mut Vec<Module>,
+) -> Result<(), ConfigError> {
+    let items = std::mem::take(&mut file.items);
+    let index = modules.len();
+    modules.push(Module {
+        path: path.clone(),
+        source: source.to_path_buf(),
+        file,
+    });
+
+    for item in items {
+        let item_mod = match item {
+            Item::Mod(item_mod) if !is_cfg_test(&item_mod.attrs) => item_mod,
+            Item::Mod(_) => continue,
+            item => {
+                modules[index].file.items.push(item);
+                continue;
+            }
+        };
+        let mut module_path = path.clone();
+        module_path.push(item_mod.ident.unraw().to_string());
+        let path_attribute = path_attribute(&item_mod.attrs);
+        match item_mod.content {
+            Some((_, items)) => {
+                let dir = match path_attribute {
+                    Some(path) => dir.join(path),
+                    None => dir.join(item_mod.ident.unraw().to_string()),
+                };
+                let file = syn::File {
+                    shebang: None,
+                    attrs: Vec::new(),
+                    items,
+                };
+                add_module(file, source, module_path, &dir, &dir, modules)?;
+            }
+            None => {
+                let path = match path_attribute {
+                    Some(path) => path_dir.join(path),
+                    None => module_file(&item_mod, dir)?,
+                };
+                add_module_file(&path, module_path, modules)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds the file of `mod name;` in `dir`, which is either `name.rs` or `name/mod.rs`.
+fn module_file(item_mod: &ItemMod, dir: &Path) -> io::Result<PathBuf> {
+    let name = item_mod.ident.to_string();
+    [
+        dir.join(format!("{name}.rs")),
+        dir.join(name).join("mod.rs"),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+    .ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "file not found for module `{}` in {}",
+                item_mod.ident,
+                dir.display()
+            ),
+        )
+    })
+}
+
+fn path_attribute(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match attr.parse_meta() {
+        Ok(Meta::NameValue(meta)) if meta.path.is_ident("path") => match meta.lit {
+            Lit::Str(lit) => Some(lit.value()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Test modules are skipped, as they rarely define types to convert.
+fn is_cfg_test(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path.is_ident("cfg") && attr.tokens.to_string() == "(test)")
+}
+
+/// What the names used in a module refer to: the paths imported by its `use` items, made absolute
+/// from the path of the module itself when it is known.
+#[derive(Debug, Default)]
+pub(crate) struct Scope {
+    module: Option<Vec<String>>,
+    imports: HashMap<String, Vec<String>>,
+    globs: Vec<Vec<String>>,
+}
+
+impl Scope {
+    pub(crate) fn new(module: Option<Vec<String>>, file: &syn::File) -> Self {
+        let mut scope = Self {
+            module,
+            ..Default::default()
+        };
+        for item in &file.items {
+            if let Item::Use(item_use) = item {
+                scope.add_use_tree(&item_use.tree, Vec::new());
+            }
+        }
+        scope
+    }
+
+    pub(crate) fn module(&self) -> Option<&[String]> {
+        self.module.as_deref()
+    }
+
+    /// Returns the modules whose items `use module::*` brings into scope.
+    pub(crate) fn globs(&self) -> &[Vec<String>] {
+        &self.globs
+    }
+
+    fn add_use_tree(&mut self, tree: &UseTree, mut prefix: Vec<String>) {
+        match tree {
+            UseTree::Path(use_path) => {
+                prefix.push(use_path.ident.unraw().to_string());
+                self.add_use_tree(&use_path.tree, prefix);
+            }
+            UseTree::Name(use_name) if use_name.ident == "self" => {
+                if let Some(name) = prefix.last().cloned() {
+                    self.add_import(name, prefix);
+                }
+            }
+            UseTree::Name(use_name) => {
+                let name = use_name.ident.unraw().to_string();
+                prefix.push(name.clone());
+                self.add_import(name, prefix);
+            }
+            UseTree::Rename(use_rename) => {
+                prefix.push(use_rename.ident.unraw().to_string());
+                self.add_import(use_rename.rename.unraw().to_string(), prefix);
+            }
+            UseTree::Glob(_) => {
+                if let Some(path) = self.absolute(prefix) {
+                    self.globs.push(path);
+                }
+            }
+            UseTree::Group(use_group) => {
+                for tree in &use_group.items {
+                    self.add_use_tree(tree, prefix.clone());
+                }
+            }
+        }
+    }
+
+    fn add_import(&mut self, name: String, path: Vec<String>) {
+        if let Some(path) = self.absolute(path) {
+            self.imports.insert(name, path);
+        }
+    }
+
+    /// Returns the absolute path `path` refers to, if it can be told without knowing every item
+    /// in scope. A single name resolves only if it is imported.
+    pub(crate) fn resolve(&self, path: &syn::Path) -> Option<Vec<String>> {
+        let segments: Vec<_> = path
+            .segments
+            .iter()
+            .map(|segment| segment.ident.unraw().to_string())
+            .collect();
+        if segments.len() == 1 && path.leading_colon.is_none() {
+            return self.imports.get(&segments[0]).cloned();
+        }
+        if path.leading_colon.is_some() {
+            return Some(segments);
+        }
+        self.absolute(segments)
+    }
+
+    /// Returns `resolved`, a path given by `resolve`, relative to the module of the scope if it
+    /// could be one. Paths not starting with `crate` may name a submodule rather than a crate,
+    /// as in `inner::Config`, which only the items of the module can tell.
+    pub(crate) fn relative(&self, resolved: &[String]) -> Option<Vec<String>> {
+        if resolved.len() < 2 || resolved[0] == "crate" {
+            return None;
+        }
+        let mut relative = self.module.clone()?;
+        relative.extend(resolved.iter().cloned());
+        Some(relative)
+    }
+
+    /// Makes `path` absolute, resolving `self` and `super` against the path of the module and
+    /// names imported into it. Paths starting with any other name are taken to be in other crates.
+    fn absolute(&self, mut path: Vec<String>) -> Option<Vec<String>> {
+        let first = path.first()?;
+        match first.as_str() {
+            "crate" => Some(path),
+            "self" | "super" => {
+                let mut absolute = self.module.clone()?;
+                while path.first().is_some_and(|segment| segment == "super") {
+                    path.remove(0);
+                    absolute.pop().filter(|_| !absolute.is_empty())?;
+                }
+                if path.first().is_some_and(|segment| segment == "self") {
+                    path.remove(0);
+                }
+                absolute.extend(path);
+                Some(absolute)
+            }
+            _ => match self.imports.get(first) {
+                Some(import) => {
+                    let mut absolute = import.clone();
+                    absolute.extend(path.drain(1..));
+                    Some(absolute)
+                }
+                None => Some(path),
+            },
+        }
+    }
+}
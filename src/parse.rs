@@ -1,22 +1,27 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 
 use crate::types::{
     Enumerator, Field, FieldType, FileDescriptor, Frequency, Message, OneOf, Syntax,
 };
+use quote::ToTokens;
 use syn::visit::{self, Visit};
 use syn::{
-    self, Fields, FieldsNamed, File, GenericArgument, Ident, ItemEnum, ItemStruct, PathArguments,
-    Type, TypePath, Variant,
+    self, Attribute, Fields, FieldsNamed, File, Ident, ItemEnum, ItemStruct, Type, TypePath,
+    Variant,
 };
 
 use super::Context;
-use crate::extract::{self, Extract};
+use crate::diagnostic::Diagnostic;
+use crate::extract::{self, Collection, Extract};
+use crate::module::Scope;
 
 struct RequiredImportsCollector<'a> {
     context: &'a Context,
+    scope: Scope,
     imports: BTreeSet<String>,
+    well_known_imports: BTreeSet<String>,
 }
 
 impl<'a> Extract for RequiredImportsCollector<'a> {
@@ -34,14 +39,41 @@ impl<'a> Extract for RequiredImportsCollector<'a> {
 }
 
 impl<'a, 'ast> Visit<'ast> for RequiredImportsCollector<'a> {
+    fn visit_type(&mut self, typ: &'ast Type) {
+        if let Type::Path(type_path) = typ {
+            if let Some(package) = self
+                .context
+                .get_instance(&self.scope, typ)
+                .and_then(|ident| {
+                    self.context
+                        .get_package(&self.scope, &type_path.path, Some(ident))
+                })
+            {
+                self.imports.insert(package.to_string());
+            }
+        }
+        visit::visit_type(self, typ);
+    }
+
     fn visit_type_path(&mut self, type_path: &TypePath) {
         let ident = type_path_ident(type_path);
+        if extract::pointee(&Type::Path(type_path.clone())).is_some() {
+            // pointers are transparent, so only the type they point to is looked up.
+            return visit::visit_type_path(self, type_path);
+        }
         if !self
             .context
             .type_replacement
             .contains_key(ident.to_string().as_str())
         {
-            if let Some(package) = self.context.get_package(ident) {
+            let ty = Type::Path(type_path.clone());
+            if let Ok(Some((_, path))) = self.context.well_known_type(&self.scope, &ty) {
+                self.well_known_imports.insert(path.to_string());
+            } else if self.context.is_bytes(&self.scope, &ty) {
+                // byte buffers are scalars, whatever items share their name.
+            } else if let Some(package) =
+                self.context.get_package(&self.scope, &type_path.path, None)
+            {
                 self.imports.insert(package.to_string());
             }
         }
@@ -49,18 +81,34 @@ impl<'a, 'ast> Visit<'ast> for RequiredImportsCollector<'a> {
     }
 }
 
-pub fn collect_required_imports(context: &Context, file: &File) -> BTreeSet<String> {
+/// Returns the proto files `file` depends on: those of the packages it refers to, and those
+/// defining the well-known types it uses.
+pub fn collect_required_imports(context: &Context, file: &File) -> BTreeSet<PathBuf> {
     let mut collector = RequiredImportsCollector {
         context,
+        scope: Scope::new(context.current_module(), file),
         imports: Default::default(),
+        well_known_imports: Default::default(),
     };
     extract::extract_from_file(&mut collector, file);
-    collector.imports
+    collector
+        .imports
+        .into_iter()
+        .map(|s| Path::new(&s.replace('.', "/")).with_extension("proto"))
+        .chain(collector.well_known_imports.into_iter().map(PathBuf::from))
+        .collect()
 }
 
 struct SchemaFileBuilder<'a> {
     context: &'a Context,
+    scope: Scope,
     file_descriptor: FileDescriptor,
+    wrappers: BTreeSet<String>,
+    bytes_fields: BTreeSet<String>,
+    borrowed_fields: BTreeMap<String, Vec<(String, i32)>>,
+    /// The struct or enum being extracted, to report diagnostics in.
+    item: String,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Extract for SchemaFileBuilder<'a> {
@@ -69,15 +117,34 @@ impl<'a> Extract for SchemaFileBuilder<'a> {
         item_struct: &ItemStruct,
         fields_named: &FieldsNamed,
     ) {
-        let fields = fields_named
+        let ident = &item_struct.ident;
+        self.item = ident.to_string();
+        let scope = self.scope(ident);
+        let Some(reserved) = self.attribute_reserved(ident, &item_struct.attrs) else {
+            return;
+        };
+        let Some(fields) = self.fields_to_schema(ident, &scope, fields_named, &reserved.0) else {
+            return;
+        };
+        let Some((reserved_nums, reserved_names)) = self.reserved(ident, &scope, reserved, &fields)
+        else {
+            return;
+        };
+        let borrowed: Vec<_> = fields_named
             .named
             .iter()
-            .enumerate()
-            .map(|(i, field)| self.field_to_schema(field, i + 1))
+            .zip(&fields)
+            .filter(|(field, _)| extract::borrows_bytes(field))
+            .map(|(_, field)| (field.name.clone(), field.number))
             .collect();
+        if !borrowed.is_empty() {
+            self.borrowed_fields.insert(ident.to_string(), borrowed);
+        }
         self.add_message(Message {
             name: item_struct.ident.to_string(),
             fields,
+            reserved_nums,
+            reserved_names,
             ..Message::default()
         });
     }
@@ -88,58 +155,99 @@ impl<'a> Extract for SchemaFileBuilder<'a> {
         variant: &Variant,
         fields_named: &FieldsNamed,
     ) {
-        let fields = fields_named
-            .named
-            .iter()
-            .enumerate()
-            .map(|(i, field)| self.field_to_schema(field, i + 1))
-            .collect();
+        let scope = format!("{}.{}Inner", self.scope(&item_enum.ident), variant.ident);
+        let ident = &variant.ident;
+        let Some(fields) = self.fields_to_schema(ident, &scope, fields_named, &[]) else {
+            return;
+        };
+        let Some((reserved_nums, reserved_names)) =
+            self.reserved(ident, &scope, Default::default(), &fields)
+        else {
+            return;
+        };
         self.add_nested_message(
             &item_enum.ident,
             Message {
-                name: format!("{}Inner", &variant.ident),
+                name: format!("{}Inner", variant.ident),
                 fields,
+                reserved_nums,
+                reserved_names,
                 ..Message::default()
             },
         );
     }
 
     fn extract_nested_message_with_fields_unit(&mut self, item_enum: &ItemEnum, variant: &Variant) {
+        let scope = format!("{}.{}Inner", self.scope(&item_enum.ident), variant.ident);
+        let Some((reserved_nums, reserved_names)) =
+            self.reserved::<Field>(&variant.ident, &scope, Default::default(), &[])
+        else {
+            return;
+        };
         self.add_nested_message(
             &item_enum.ident,
             Message {
-                name: format!("{}Inner", &variant.ident),
+                name: format!("{}Inner", variant.ident),
+                reserved_nums,
+                reserved_names,
                 ..Message::default()
             },
         );
     }
 
     fn extract_one_of(&mut self, item_enum: &ItemEnum) {
-        let fields: Vec<_> = item_enum
+        let ident = &item_enum.ident;
+        self.item = ident.to_string();
+        let scope = self.scope(ident);
+        let Some(reserved) = self.attribute_reserved(ident, &item_enum.attrs) else {
+            return;
+        };
+        let Some(numbers) = self.field_numbers(
+            ident,
+            &scope,
+            item_enum
+                .variants
+                .iter()
+                .map(|variant| (variant.ident.to_string(), variant.attrs.as_slice())),
+            &reserved.0,
+        ) else {
+            return;
+        };
+
+        let fields: Option<Vec<_>> = item_enum
             .variants
             .iter()
-            .enumerate()
-            .map(|(i, variant)| {
-                let i = i + 1;
-                if let Fields::Unnamed(fields_unnamed) = &variant.fields {
-                    let mut field = fields_unnamed.unnamed.clone().pop().unwrap().into_value();
+            .zip(numbers)
+            .map(|(variant, number)| match &variant.fields {
+                Fields::Unnamed(fields_unnamed) if fields_unnamed.unnamed.len() == 1 => {
+                    let mut field = fields_unnamed.unnamed[0].clone();
                     field.ident = Some(variant.ident.clone());
-                    self.field_to_schema(&field, i)
-                } else {
-                    Field {
-                        name: variant.ident.to_string(),
-                        typ: FieldType::MessageOrEnum(format!("{}Inner", variant.ident)),
-                        number: i as i32,
-                        frequency: Frequency::Required,
-                        default: None,
-                        packed: None,
-                        boxed: false,
-                        deprecated: false,
-                    }
+                    self.one_of_field(&scope, &field, number)
+                        .map_err(|e| self.report(&e, Some(&variant.ident)))
+                        .ok()
                 }
+                _ => Some(Field {
+                    name: variant.ident.to_string(),
+                    typ: FieldType::MessageOrEnum(format!("{}Inner", variant.ident)),
+                    number,
+                    frequency: Frequency::Required,
+                    default: None,
+                    packed: None,
+                    boxed: false,
+                    deprecated: false,
+                }),
             })
-            .collect();
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect::<Option<_>>();
+        let Some(fields) = fields else {
+            return;
+        };
 
+        let Some((reserved_nums, reserved_names)) = self.reserved(ident, &scope, reserved, &fields)
+        else {
+            return;
+        };
         self.add_message(Message {
             name: item_enum.ident.to_string(),
             oneofs: vec![OneOf {
@@ -147,77 +255,256 @@ impl<'a> Extract for SchemaFileBuilder<'a> {
                 fields,
                 ..OneOf::default()
             }],
+            reserved_nums,
+            reserved_names,
             ..Message::default()
         });
     }
 
+    fn extract_error(&mut self, ident: &Ident, error: syn::Error) {
+        self.item = ident.to_string();
+        self.report(&error, None);
+    }
+
     fn extract_enumerator(&mut self, item_enum: &ItemEnum) {
-        let fields = item_enum
+        let ident = &item_enum.ident;
+        self.item = ident.to_string();
+        let scope = self.scope(ident);
+        let Some(reserved) = self.attribute_reserved(ident, &item_enum.attrs) else {
+            return;
+        };
+        let names: Vec<_> = item_enum
             .variants
             .iter()
-            .enumerate()
-            .map(|(i, variant)| (variant.ident.to_string(), i as i32))
+            .map(|v| v.ident.to_string())
             .collect();
+        let values = match extract::enum_values(item_enum) {
+            Ok(values) => values,
+            Err(e) => return self.report_item(ident, format!("invalid enum values: {e}")),
+        };
+        // explicit values take precedence over the lock file, as they mirror the Rust enum.
+        let mut fields: Vec<_> = match (values, self.context.lock.get(&scope)) {
+            (Some(values), _) => names.into_iter().zip(values).collect(),
+            (None, Some(locked)) => {
+                let mut next = locked.values().max().map_or(0, |n| n + 1);
+                names
+                    .into_iter()
+                    .map(|name| {
+                        let value = locked.get(&name).copied().unwrap_or_else(|| {
+                            while reserved.0.contains(&next) {
+                                next += 1;
+                            }
+                            next += 1;
+                            next - 1
+                        });
+                        (name, value)
+                    })
+                    .collect()
+            }
+            (None, None) => {
+                let values = (0..).filter(|value| !reserved.0.contains(value));
+                names.into_iter().zip(values).collect()
+            }
+        };
+        // proto3 requires the first enum value to be zero.
+        match fields.iter().position(|(_, value)| *value == 0) {
+            Some(i) => {
+                let zero = fields.remove(i);
+                fields.insert(0, zero);
+            }
+            None => {
+                // explicit values are checked to include 0 by `extract::enum_values`.
+                let cause = if reserved.0.contains(&0) {
+                    "0 is reserved: do not reserve it"
+                } else {
+                    "the one locked at 0 was removed: give another `#[protobuf_gen(value = 0)]`"
+                };
+                let message =
+                    format!("proto3 requires one of the variants to have the value 0, but {cause}");
+                return self.report_item(ident, message);
+            }
+        }
+
+        let Some((reserved_nums, reserved_names)) = self.reserved(ident, &scope, reserved, &fields)
+        else {
+            return;
+        };
         self.add_enum(Enumerator {
             name: item_enum.ident.to_string(),
             fields,
+            reserved_nums,
+            reserved_names,
+            prefix_values: self.context.prefix_enum_values
+                || syn_util::contains_attribute(
+                    &item_enum.attrs,
+                    &["protobuf_gen", "prefix_values"],
+                ),
             ..Enumerator::default()
         });
     }
 }
 
+/// The reserved numbers and names of a message or enum.
+type Reserved = (Option<Vec<i32>>, Option<Vec<String>>);
+
+/// A message field or an enum value, as far as reservations are concerned.
+trait NumberedItem {
+    fn name(&self) -> &str;
+    fn number(&self) -> i32;
+}
+
+impl NumberedItem for Field {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn number(&self) -> i32 {
+        self.number
+    }
+}
+
+impl NumberedItem for (String, i32) {
+    fn name(&self) -> &str {
+        &self.0
+    }
+
+    fn number(&self) -> i32 {
+        self.1
+    }
+}
+
 pub(crate) fn type_path_ident(type_path: &TypePath) -> &Ident {
     &type_path.path.segments.last().unwrap().ident
 }
 
-pub(crate) fn generic_type_of(type_path: &TypePath) -> Option<&Type> {
-    type_path.path.segments.last().and_then(|x| {
-        if let PathArguments::AngleBracketed(g) = &x.arguments {
-            g.args.first().and_then(|x| {
-                if let GenericArgument::Type(g) = x {
-                    Some(g)
-                } else {
-                    None
-                }
-            })
-        } else {
-            None
-        }
-    })
+fn is_u8(typ: &Type) -> bool {
+    matches!(typ, Type::Path(type_path) if type_path.path.is_ident("u8"))
 }
 
-fn type_frequency(typ: &Type) -> Frequency {
+/// Only integral and string types can be used as keys of a protobuf map.
+fn is_valid_map_key(typ: &FieldType) -> bool {
     match typ {
-        Type::Array(_) => Frequency::Repeated,
-        Type::Path(type_path) => {
-            let ident = type_path_ident(type_path);
-            if ident == "Vec" || ident == "HashSet" {
-                Frequency::Repeated
-            } else if ident == "Option" {
-                Frequency::Optional
-            } else {
-                Frequency::Required
-            }
+        FieldType::Bool | FieldType::String_ | FieldType::StringCow => true,
+        FieldType::MessageOrEnum(s) => matches!(
+            s.as_str(),
+            "int32"
+                | "int64"
+                | "uint32"
+                | "uint64"
+                | "sint32"
+                | "sint64"
+                | "bool"
+                | "fixed32"
+                | "fixed64"
+                | "sfixed32"
+                | "sfixed64"
+                | "string"
+        ),
+        typ => is_integer(typ),
+    }
+}
+
+fn is_integer(typ: &FieldType) -> bool {
+    matches!(
+        typ,
+        FieldType::Int32
+            | FieldType::Int64
+            | FieldType::Uint32
+            | FieldType::Uint64
+            | FieldType::Sint32
+            | FieldType::Sint64
+            | FieldType::Fixed32
+            | FieldType::Fixed64
+            | FieldType::Sfixed32
+            | FieldType::Sfixed64
+    )
+}
+
+/// Encodes an integer field, or the values of an integer map, as the scalar type `encoding`.
+fn encode(typ: FieldType, encoding: &str) -> Result<FieldType, String> {
+    match typ {
+        FieldType::Map(key, value) => Ok(FieldType::Map(key, Box::new(encode(*value, encoding)?))),
+        typ if is_integer(&typ) => Ok(match encoding {
+            "int32" => FieldType::Int32,
+            "int64" => FieldType::Int64,
+            "uint32" => FieldType::Uint32,
+            "uint64" => FieldType::Uint64,
+            "sint32" => FieldType::Sint32,
+            "sint64" => FieldType::Sint64,
+            "fixed32" => FieldType::Fixed32,
+            "fixed64" => FieldType::Fixed64,
+            "sfixed32" => FieldType::Sfixed32,
+            "sfixed64" => FieldType::Sfixed64,
+            _ => unreachable!(),
+        }),
+        _ => Err(format!("encoding \"{encoding}\" only applies to integers")),
+    }
+}
+
+/// Returns whether the message of a field with type `typ` is behind a pointer. Repeated fields
+/// are never boxed.
+fn is_boxed(typ: &Type) -> bool {
+    match Collection::of(typ) {
+        Some(Collection::Optional) => {
+            extract::element_type(typ).is_some_and(|x| extract::pointee(x).is_some())
         }
-        _ => panic!(),
+        Some(_) => false,
+        None => extract::pointee(typ).is_some(),
+    }
+}
+
+fn type_frequency(typ: &Type) -> Frequency {
+    match Collection::of(typ) {
+        Some(collection) if collection.is_repeated() => Frequency::Repeated,
+        Some(Collection::Optional) => Frequency::Optional,
+        _ => Frequency::Required,
     }
 }
 
 impl<'a> SchemaFileBuilder<'a> {
+    /// Returns whether a field with type `typ` holds `Bytes`, whose proxies are `Bytes` too so
+    /// that decoding from a `Bytes` buffer shares it instead of copying.
+    fn is_shared_bytes(&self, typ: &Type) -> bool {
+        let typ = match Collection::of(typ) {
+            Some(Collection::Map) => return false,
+            Some(_) => match extract::element_type(typ) {
+                Some(element) => element,
+                None => return false,
+            },
+            None => typ,
+        };
+        matches!(typ, Type::Path(type_path) if type_path_ident(type_path) == "Bytes")
+            && self.context.is_bytes(&self.scope, typ)
+    }
+
     fn add_message(&mut self, m: Message) {
         debug!("Message {}", m.name);
         self.file_descriptor.messages.push(m);
     }
 
+    /// Adds `m` to the message of the oneof `parent`, which is missing only if it had errors.
     fn add_nested_message(&mut self, parent: &Ident, m: Message) {
         debug!("Message(in {}) {}", parent, m.name);
-        self.file_descriptor
+        if let Some(parent) = self
+            .file_descriptor
             .messages
             .iter_mut()
             .find(|m| parent == &m.name)
-            .unwrap_or_else(|| panic!("no parent message \"{parent}\" exists."))
-            .messages
-            .push(m);
+        {
+            parent.messages.push(m);
+        }
+    }
+
+    /// Records an error in a field or variant of the current item, or in the item itself.
+    fn report(&mut self, error: &syn::Error, field: Option<&Ident>) {
+        let field = field.map(Ident::to_string);
+        self.diagnostics
+            .push(Diagnostic::new(error, &self.item, field));
+    }
+
+    fn report_item(&mut self, ident: &Ident, message: String) {
+        self.diagnostics
+            .push(Diagnostic::new_spanned(ident.span(), &self.item, message));
     }
 
     fn add_enum(&mut self, e: Enumerator) {
@@ -225,9 +512,32 @@ impl<'a> SchemaFileBuilder<'a> {
         self.file_descriptor.enums.push(e);
     }
 
-    fn type_field_type(&self, typ: &Type) -> FieldType {
+    fn type_field_type(&mut self, typ: &Type) -> syn::Result<FieldType> {
+        if self.context.is_bytes(&self.scope, typ) {
+            return Ok(FieldType::Bytes_);
+        }
+        if let Some(base) = extract::non_zero_base(typ) {
+            return self.type_field_type(&base);
+        }
+        if let Some(borrowed) = extract::borrowed(typ) {
+            return match borrowed {
+                Type::Path(type_path) if type_path.path.is_ident("str") => Ok(FieldType::StringCow),
+                Type::Slice(type_slice) if is_u8(&type_slice.elem) => Ok(FieldType::BytesCow),
+                Type::Path(_) if matches!(typ, Type::Path(_)) => self.type_field_type(borrowed),
+                _ => Err(unsupported(typ, "only `str` and `[u8]` can be borrowed")),
+            };
+        }
+        if let Some(pointee) = extract::pointee(typ) {
+            if Collection::of(pointee).is_some() {
+                return Err(unsupported(
+                    typ,
+                    "pointers to collections cannot be converted",
+                ));
+            }
+            return self.type_field_type(pointee);
+        }
         match typ {
-            Type::Array(type_array) => self.type_field_type(&type_array.elem),
+            Type::Array(type_array) => self.element_field_type(&type_array.elem),
             Type::Path(type_path) => {
                 let ident = type_path_ident(type_path);
                 if let Some(ty) = self
@@ -235,122 +545,516 @@ impl<'a> SchemaFileBuilder<'a> {
                     .type_replacement
                     .get(ident.to_string().as_str())
                 {
-                    ty.clone()
-                } else if ident == "Vec" || ident == "HashSet" || ident == "Option" {
-                    self.type_field_type(generic_type_of(type_path).unwrap())
-                } else if let Some(package) = self.context.get_package(ident) {
-                    FieldType::MessageOrEnum(format!("{package}.{ident}"))
+                    Ok(ty.clone())
+                } else if let Some((proto, _)) = self.context.well_known_type(&self.scope, typ)? {
+                    Ok(FieldType::MessageOrEnum(proto.to_string()))
+                } else if let Some(ident) = self.context.get_instance(&self.scope, typ) {
+                    let name =
+                        self.context
+                            .get_type_name(&self.scope, &type_path.path, Some(ident))?;
+                    Ok(FieldType::MessageOrEnum(
+                        name.unwrap_or_else(|| ident.to_string()),
+                    ))
+                } else if let Some(collection) = Collection::of(typ) {
+                    match collection {
+                        Collection::Map => self.map_field_type(typ),
+                        _ => match extract::element_type(typ) {
+                            Some(element) => self.element_field_type(element),
+                            None => Err(unsupported(typ, "the element type is missing")),
+                        },
+                    }
+                } else if !type_path.path.segments.last().unwrap().arguments.is_none() {
+                    Err(unsupported(
+                        typ,
+                        &format!(
+                            "no instance of `{ident}` matches it; add one to the `instantiate` \
+                             attribute of `{ident}`"
+                        ),
+                    ))
+                } else if let Some(name) =
+                    self.context
+                        .get_type_name(&self.scope, &type_path.path, None)?
+                {
+                    Ok(FieldType::MessageOrEnum(name))
                 } else {
-                    FieldType::MessageOrEnum(ident.to_string())
+                    Ok(FieldType::MessageOrEnum(ident.to_string()))
                 }
             }
-            _ => panic!("failed to parse: {typ:?}"),
+            Type::Reference(_) => Err(unsupported(typ, "only `str` and `[u8]` can be borrowed")),
+            Type::Tuple(_) => Err(unsupported(
+                typ,
+                "tuples cannot be converted, use a tuple struct instead",
+            )),
+            Type::TraitObject(_) | Type::ImplTrait(_) => {
+                Err(unsupported(typ, "trait objects cannot be converted"))
+            }
+            _ => Err(unsupported(typ, "the type cannot be converted")),
+        }
+    }
+
+    fn map_field_type(&mut self, typ: &Type) -> syn::Result<FieldType> {
+        let (key, value) = extract::map_types(typ)
+            .ok_or_else(|| unsupported(typ, "the key and value types are missing"))?;
+
+        let key_type = self.type_field_type(key)?;
+        if Collection::of(key).is_some() || !is_valid_map_key(&key_type) {
+            return Err(syn::Error::new_spanned(
+                key,
+                format!(
+                    "invalid map key type `{}`: keys must be integral or string types",
+                    key.to_token_stream()
+                ),
+            ));
         }
+
+        Ok(FieldType::Map(
+            Box::new(key_type),
+            Box::new(self.element_field_type(value)?),
+        ))
     }
 
-    fn field_to_schema(&self, field: &syn::Field, number: usize) -> Field {
+    /// Returns the type of an element of a collection, which is a wrapper message if the
+    /// element is a collection itself.
+    fn element_field_type(&mut self, typ: &Type) -> syn::Result<FieldType> {
+        if Collection::of(typ).is_none() {
+            return self.type_field_type(typ);
+        }
+
+        // the type is checked first, as wrappers can only be named after supported types.
+        let field_type = self.type_field_type(typ)?;
+        let name = extract::wrapper_name(typ)?;
+        if !self.wrappers.contains(&name) {
+            if self.context.defines(&name) {
+                return Err(syn::Error::new_spanned(
+                    typ,
+                    format!(
+                        "wrapper message `{name}` for `{}` conflicts with an item of the same name",
+                        typ.to_token_stream()
+                    ),
+                ));
+            }
+            self.wrappers.insert(name.clone());
+
+            let collection = Collection::of(typ).unwrap();
+            let field = Field {
+                name: collection.wrapper_field().to_string(),
+                frequency: type_frequency(typ),
+                typ: field_type,
+                number: 1,
+                default: None,
+                packed: None,
+                boxed: false,
+                deprecated: false,
+            };
+            self.add_message(Message {
+                name: name.clone(),
+                fields: vec![field],
+                ..Message::default()
+            });
+        }
+        Ok(FieldType::MessageOrEnum(name))
+    }
+
+    fn scope(&self, name: &Ident) -> String {
+        format!("{}.{}", self.context.current_package, name)
+    }
+
+    /// Numbers fields by their explicit tag, then by the lock file if `scope` was generated
+    /// before, and by position otherwise. Fields missing from the lock get fresh numbers.
+    fn field_numbers<'f, I>(
+        &mut self,
+        ident: &Ident,
+        scope: &str,
+        fields: I,
+        reserved: &[i32],
+    ) -> Option<Vec<i32>>
+    where
+        I: IntoIterator<Item = (String, &'f [Attribute])>,
+    {
+        let numbers = self.lock_field_numbers(scope, fields, reserved);
+        numbers
+            .map_err(|e| self.report_item(ident, format!("invalid field numbers: {e}")))
+            .ok()
+    }
+
+    fn lock_field_numbers<'f, I>(
+        &self,
+        scope: &str,
+        fields: I,
+        reserved: &[i32],
+    ) -> Result<Vec<i32>, String>
+    where
+        I: IntoIterator<Item = (String, &'f [Attribute])>,
+    {
+        let locked = match self.context.lock.get(scope) {
+            Some(locked) => locked,
+            None => return extract::field_numbers(fields, reserved),
+        };
+
+        let tags = fields
+            .into_iter()
+            .map(|(name, attrs)| {
+                let tag = extract::field_tag(attrs).map_err(|e| format!("\"{name}\": {e}"))?;
+                Ok((name, tag))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // the numbers of removed fields stay reserved, so that old messages are not misread.
+        for (name, tag) in &tags {
+            let removed = locked.iter().find(|(locked_name, number)| {
+                Some(**number) == *tag && !tags.iter().any(|(name, _)| name == *locked_name)
+            });
+            if let Some((removed, number)) = removed {
+                return Err(format!(
+                    "\"{name}\": tag {number} was used by the removed field \"{removed}\", \
+                     so it is reserved"
+                ));
+            }
+        }
+
+        let mut next = locked
+            .values()
+            .chain(tags.iter().filter_map(|(_, tag)| tag.as_ref()))
+            .max()
+            .map_or(1, |n| n + 1);
+        let numbers: Vec<_> = tags
+            .into_iter()
+            .map(|(name, tag)| {
+                let number = tag
+                    .or_else(|| locked.get(&name).copied())
+                    .unwrap_or_else(|| {
+                        while reserved.contains(&next)
+                            || extract::RESERVED_FIELD_NUMBERS.contains(&next)
+                        {
+                            next = if reserved.contains(&next) {
+                                next + 1
+                            } else {
+                                extract::RESERVED_FIELD_NUMBERS.end() + 1
+                            };
+                        }
+                        next += 1;
+                        next - 1
+                    });
+                (name, number)
+            })
+            .collect();
+
+        extract::check_field_numbers(&numbers)?;
+        Ok(numbers.into_iter().map(|(_, number)| number).collect())
+    }
+
+    /// Returns the numbers and names listed in the `reserved` attribute of a message or enum.
+    fn attribute_reserved(
+        &mut self,
+        ident: &Ident,
+        attrs: &[Attribute],
+    ) -> Option<(Vec<i32>, Vec<String>)> {
+        extract::reserved(attrs)
+            .map_err(|e| self.report_item(ident, format!("invalid reserved attribute: {e}")))
+            .ok()
+    }
+
+    /// Adds the numbers and names of fields that disappeared since the lock file was written to
+    /// `reserved`, after checking that no current field uses one of the reserved ones.
+    fn reserved<T: NumberedItem>(
+        &mut self,
+        ident: &Ident,
+        scope: &str,
+        (mut numbers, mut names): (Vec<i32>, Vec<String>),
+        fields: &[T],
+    ) -> Option<Reserved> {
+        for field in fields {
+            if numbers.contains(&field.number()) || names.iter().any(|name| name == field.name()) {
+                let message = format!("\"{}\" uses a reserved field number or name", field.name());
+                self.report_item(ident, message);
+                return None;
+            }
+        }
+
+        if let Some(locked) = self.context.lock.get(scope) {
+            for (name, number) in locked {
+                if !fields.iter().any(|field| field.name() == name) {
+                    names.push(name.clone());
+                    if !fields.iter().any(|field| field.number() == *number) {
+                        numbers.push(*number);
+                    }
+                }
+            }
+        }
+
+        numbers.sort_unstable();
+        numbers.dedup();
+        names.sort();
+        names.dedup();
+        Some((
+            Some(numbers).filter(|numbers| !numbers.is_empty()),
+            Some(names).filter(|names| !names.is_empty()),
+        ))
+    }
+
+    /// Returns the fields of a message, or `None` if any of them cannot be converted, after
+    /// reporting every one that cannot.
+    fn fields_to_schema(
+        &mut self,
+        ident: &Ident,
+        scope: &str,
+        fields_named: &FieldsNamed,
+        reserved: &[i32],
+    ) -> Option<Vec<Field>> {
+        let numbers = self.field_numbers(
+            ident,
+            scope,
+            fields_named.named.iter().map(|field| {
+                (
+                    field.ident.as_ref().unwrap().to_string(),
+                    field.attrs.as_slice(),
+                )
+            }),
+            reserved,
+        )?;
+
+        fields_named
+            .named
+            .iter()
+            .zip(numbers)
+            .map(|(field, number)| {
+                if self.is_shared_bytes(&field.ty) {
+                    let name = field.ident.as_ref().unwrap();
+                    self.bytes_fields.insert(format!(".{scope}.{name}"));
+                }
+                self.field_to_schema(field, number)
+                    .map_err(|e| self.report(&e, field.ident.as_ref()))
+                    .ok()
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Converts the field of a single-element oneof variant. Oneof fields can't be `repeated`,
+    /// `optional` or `map`, so collections are wrapped in a message, as they are inside other
+    /// collections.
+    fn one_of_field(&mut self, scope: &str, field: &syn::Field, number: i32) -> syn::Result<Field> {
+        let is_plain = !syn_util::contains_attribute(&field.attrs, &["protobuf_gen", "opaque"])
+            && syn_util::get_attribute_value::<String>(
+                &field.attrs,
+                &["protobuf_gen", "substitute"],
+            )
+            .is_none();
+        let Some(collection) = Collection::of(&field.ty).filter(|_| is_plain) else {
+            if self.is_shared_bytes(&field.ty) {
+                let name = field.ident.as_ref().unwrap();
+                self.bytes_fields.insert(format!(".{scope}.{name}"));
+            }
+            let schema = self.field_to_schema(field, number)?;
+            if schema.frequency != Frequency::Required || matches!(schema.typ, FieldType::Map(..)) {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "a oneof field can't be repeated, optional or a map",
+                ));
+            }
+            return Ok(schema);
+        };
+
+        let typ = self.element_field_type(&field.ty)?;
+        if let FieldType::MessageOrEnum(wrapper) = &typ {
+            if self.is_shared_bytes(&field.ty) {
+                self.bytes_fields.insert(format!(
+                    ".{}.{wrapper}.{}",
+                    self.context.current_package,
+                    collection.wrapper_field()
+                ));
+            }
+        }
+        Ok(Field {
+            name: field.ident.as_ref().unwrap().to_string(),
+            typ,
+            number,
+            frequency: Frequency::Required,
+            default: None,
+            packed: None,
+            boxed: false,
+            deprecated: false,
+        })
+    }
+
+    fn field_to_schema(&mut self, field: &syn::Field, number: i32) -> syn::Result<Field> {
         if let Some(substitute) =
             syn_util::get_attribute_value::<String>(&field.attrs, &["protobuf_gen", "substitute"])
         {
-            return Field {
+            return Ok(Field {
                 name: field.ident.as_ref().unwrap().to_string(),
                 frequency: Frequency::Required,
                 typ: FieldType::MessageOrEnum(substitute),
-                number: number as i32,
+                number,
                 default: None,
                 packed: None,
                 boxed: false,
                 deprecated: false,
-            };
+            });
         }
 
         if syn_util::contains_attribute(&field.attrs, &["protobuf_gen", "opaque"]) {
-            return Field {
+            return Ok(Field {
                 name: field.ident.as_ref().unwrap().to_string(),
                 frequency: type_frequency(&field.ty),
                 typ: FieldType::BytesCow,
-                number: number as i32,
+                number,
                 default: None,
                 packed: None,
                 boxed: false,
                 deprecated: false,
-            };
+            });
         }
 
-        Field {
-            name: field.ident.as_ref().unwrap().to_string(),
+        let name = field.ident.as_ref().unwrap().to_string();
+        let typ = self.type_field_type(&field.ty)?;
+        let typ = match extract::integer_encoding(&field.attrs) {
+            Ok(Some(encoding)) => encode(typ, &encoding),
+            Ok(None) => Ok(typ),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| syn::Error::new_spanned(field, e))?;
+
+        Ok(Field {
+            name,
             frequency: type_frequency(&field.ty),
-            typ: self.type_field_type(&field.ty),
-            number: number as i32,
+            typ,
+            number,
             default: None,
             packed: None,
-            boxed: false,
+            boxed: is_boxed(&field.ty),
             deprecated: false,
-        }
+        })
     }
 }
 
-pub fn build_schema_file(context: &Context, file: &File) -> SchemaFile {
+fn unsupported(typ: &Type, reason: &str) -> syn::Error {
+    let message = format!("unsupported type `{}`: {reason}", typ.to_token_stream());
+    syn::Error::new_spanned(typ, message)
+}
+
+/// Builds the schema of the items in `file`, or returns every construct that cannot be converted.
+/// The paths of the diagnostics are left empty, as only the caller knows where `file` is.
+pub fn build_schema_file(context: &Context, file: &File) -> Result<SchemaFile, Vec<Diagnostic>> {
     let file_descriptor = FileDescriptor {
         syntax: Syntax::Proto3,
         import_paths: collect_required_imports(context, file)
             .into_iter()
-            .map(|s| Path::new(&s.replace('.', "/")).with_extension("proto"))
             .collect(),
         ..Default::default()
     };
 
     let mut builder = SchemaFileBuilder {
         context,
+        scope: Scope::new(context.current_module(), file),
         file_descriptor,
+        wrappers: BTreeSet::new(),
+        bytes_fields: BTreeSet::new(),
+        borrowed_fields: BTreeMap::new(),
+        item: String::new(),
+        diagnostics: Vec::new(),
     };
     extract::extract_from_file(&mut builder, file);
 
-    SchemaFile(builder.file_descriptor)
+    if !builder.diagnostics.is_empty() {
+        return Err(builder.diagnostics);
+    }
+    Ok(SchemaFile {
+        file_descriptor: builder.file_descriptor,
+        wrappers: builder.wrappers,
+        bytes_fields: builder.bytes_fields,
+        borrowed_fields: builder.borrowed_fields,
+    })
 }
 
-pub struct SchemaFile(FileDescriptor);
+pub struct SchemaFile {
+    file_descriptor: FileDescriptor,
+    /// Wrapper messages synthesized for nested collections, which the files of a package share.
+    wrappers: BTreeSet<String>,
+    /// Paths of the fields whose proxies are `Bytes`, such as `.package.Message.field`.
+    bytes_fields: BTreeSet<String>,
+    /// Names and numbers of the fields the derived encoding writes from where they are borrowed,
+    /// by the messages they are in.
+    borrowed_fields: BTreeMap<String, Vec<(String, i32)>>,
+}
 
 impl Deref for SchemaFile {
     type Target = FileDescriptor;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.file_descriptor
     }
 }
 
 impl DerefMut for SchemaFile {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.file_descriptor
     }
 }
 
 impl Default for SchemaFile {
     fn default() -> Self {
-        Self(FileDescriptor {
-            syntax: Syntax::Proto3,
-            ..Default::default()
-        })
+        Self {
+            file_descriptor: FileDescriptor {
+                syntax: Syntax::Proto3,
+                ..Default::default()
+            },
+            wrappers: BTreeSet::new(),
+            bytes_fields: BTreeSet::new(),
+            borrowed_fields: BTreeMap::new(),
+        }
     }
 }
 
 impl SchemaFile {
     pub fn new(imports: Vec<PathBuf>) -> Self {
-        Self(FileDescriptor {
-            import_paths: imports,
-            ..Default::default()
-        })
+        Self {
+            file_descriptor: FileDescriptor {
+                import_paths: imports,
+                ..Default::default()
+            },
+            wrappers: BTreeSet::new(),
+            bytes_fields: BTreeSet::new(),
+            borrowed_fields: BTreeMap::new(),
+        }
     }
 
     pub fn merge(&mut self, other: &mut SchemaFile) {
-        self.0.import_paths.append(&mut other.0.import_paths);
-        self.0.import_paths.sort();
-        self.0.import_paths.dedup();
-        self.0.enums.append(&mut other.0.enums);
-        self.0.messages.append(&mut other.0.messages);
+        let file_descriptor = &mut self.file_descriptor;
+        file_descriptor.import_paths.append(&mut other.import_paths);
+        file_descriptor.import_paths.sort();
+        file_descriptor.import_paths.dedup();
+        file_descriptor.enums.append(&mut other.enums);
+
+        // wrappers already synthesized for another file of the package are dropped.
+        let wrappers = &self.wrappers;
+        other
+            .file_descriptor
+            .messages
+            .retain(|m| !(other.wrappers.contains(&m.name) && wrappers.contains(&m.name)));
+        file_descriptor
+            .messages
+            .append(&mut other.file_descriptor.messages);
+        self.wrappers.append(&mut other.wrappers);
+        self.bytes_fields.append(&mut other.bytes_fields);
+        self.borrowed_fields.append(&mut other.borrowed_fields);
+
+        // messages and enums are sorted by name, so the order of sources does not matter.
+        let file_descriptor = &mut self.file_descriptor;
+        file_descriptor.messages.sort_by(|a, b| a.name.cmp(&b.name));
+        file_descriptor.enums.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn bytes_fields(&self) -> &BTreeSet<String> {
+        &self.bytes_fields
+    }
+
+    pub fn borrowed_fields(&self) -> &BTreeMap<String, Vec<(String, i32)>> {
+        &self.borrowed_fields
     }
 
     pub fn release(self) -> FileDescriptor {
-        self.0
+        self.file_descriptor
     }
 }
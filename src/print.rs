@@ -1,15 +1,90 @@
 use std::fmt;
 
+use heck::ShoutySnakeCase;
+
 use crate::types::{
     Enumerator, Field, FieldType, FileDescriptor, Frequency, Message, OneOf, Syntax,
 };
 
 pub struct SchemaPrinter<'a>(pub &'a FileDescriptor);
 
+fn print_reserved(
+    numbers: &Option<Vec<i32>>,
+    names: &Option<Vec<String>>,
+    indent: usize,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    if let Some(numbers) = numbers.as_ref().filter(|numbers| !numbers.is_empty()) {
+        // collapses runs of consecutive numbers into `start to end` ranges.
+        let mut ranges: Vec<(i32, i32)> = Vec::new();
+        for &number in numbers {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == number => *end = number,
+                _ => ranges.push((number, number)),
+            }
+        }
+        let ranges: Vec<_> = ranges
+            .into_iter()
+            .map(|(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{start} to {end}")
+                }
+            })
+            .collect();
+        writeln!(
+            f,
+            "{:indent$}reserved {};",
+            "",
+            ranges.join(", "),
+            indent = indent
+        )?;
+    }
+
+    if let Some(names) = names.as_ref().filter(|names| !names.is_empty()) {
+        let names: Vec<_> = names.iter().map(|name| format!("\"{name}\"")).collect();
+        writeln!(
+            f,
+            "{:indent$}reserved {};",
+            "",
+            names.join(", "),
+            indent = indent
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the name of an enum value as it appears in the schema. Prefixed values are scoped by
+/// their enum, since proto3 enum values share the scope of the enum itself.
+fn enum_value_name(e: &Enumerator, name: &str) -> String {
+    if e.prefix_values {
+        format!(
+            "{}_{}",
+            e.name.to_shouty_snake_case(),
+            extract::proxy_variant_name(&e.name, name).to_shouty_snake_case()
+        )
+    } else {
+        name.to_string()
+    }
+}
+
 fn print_enum(e: &Enumerator, indent: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     writeln!(f, "{:indent$}enum {} {{", "", e.name, indent = indent)?;
+    let reserved_names = e
+        .reserved_names
+        .as_ref()
+        .map(|names| names.iter().map(|name| enum_value_name(e, name)).collect());
+    print_reserved(&e.reserved_nums, &reserved_names, indent + 2, f)?;
     for (name, number) in &e.fields {
-        writeln!(f, "{:indent$}  {} = {};", "", name, number, indent = indent)?;
+        writeln!(
+            f,
+            "{:indent$}  {} = {};",
+            "",
+            enum_value_name(e, name),
+            number,
+            indent = indent
+        )?;
     }
     writeln!(f, "{:indent$}}}", "", indent = indent)?;
     Ok(())
@@ -50,12 +125,19 @@ fn print_field(field: &Field, indent: usize, f: &mut fmt::Formatter<'_>) -> fmt:
         }
     }
 
+    let typ = match &field.typ {
+        FieldType::Map(key, value) => {
+            format!("map<{}, {}>", type_to_string(key), type_to_string(value))
+        }
+        typ => type_to_string(typ).to_string(),
+    };
+
     writeln!(
         f,
         "{:indent$}{}{} {} = {};",
         "",
         frequency_to_string(&field.frequency),
-        type_to_string(&field.typ),
+        typ,
         field.name,
         field.number,
         indent = indent
@@ -78,6 +160,12 @@ fn print_message(message: &Message, indent: usize, f: &mut fmt::Formatter<'_>) -
         message.name,
         indent = indent
     )?;
+    print_reserved(
+        &message.reserved_nums,
+        &message.reserved_names,
+        indent + 2,
+        f,
+    )?;
 
     for e in &message.enums {
         print_enum(e, indent + 2, f)?;
@@ -85,6 +85,9 @@ pub struct Enumerator {
     pub fields: Vec<(String, i32)>,
     pub fully_qualified_fields: Vec<(String, i32)>,
     pub partially_qualified_fields: Vec<(String, i32)>,
+    pub reserved_nums: Option<Vec<i32>>,
+    pub reserved_names: Option<Vec<String>>,
+    pub prefix_values: bool, // print values as `ENUM_NAME_VARIANT`
     pub imported: bool,
     pub package: String,
     pub module: String,
@@ -0,0 +1,104 @@
+use std::time::{Duration, SystemTime};
+
+use crate::Error;
+
+/// Conversions between Rust types and the well-known protobuf types they are generated as, used
+/// by the derived conversions of fields such as `Duration` and `SystemTime`.
+pub trait WellKnownType: Sized {
+    type Proxy;
+
+    fn into_proxy(self) -> Result<Self::Proxy, Error>;
+
+    fn from_proxy(proxy: Self::Proxy) -> Result<Self, Error>;
+}
+
+/// The proxy field a `WellKnownType` is stored in. Messages are optional in the proxy, with
+/// missing ones decoded as defaults, while enums named like well-known types are plain `i32`s.
+#[doc(hidden)]
+pub trait WellKnownField<P> {
+    fn from_value(proxy: P) -> Self;
+
+    fn into_value(self) -> P;
+}
+
+impl<P: Default> WellKnownField<P> for Option<P> {
+    fn from_value(proxy: P) -> Self {
+        Some(proxy)
+    }
+
+    fn into_value(self) -> P {
+        self.unwrap_or_default()
+    }
+}
+
+impl WellKnownField<i32> for i32 {
+    fn from_value(proxy: i32) -> Self {
+        proxy
+    }
+
+    fn into_value(self) -> i32 {
+        self
+    }
+}
+
+impl WellKnownType for Duration {
+    type Proxy = prost_types::Duration;
+
+    fn into_proxy(self) -> Result<Self::Proxy, Error> {
+        self.try_into()
+            .map_err(|e| Error::new_try_from_error("google.protobuf.Duration", e))
+    }
+
+    fn from_proxy(proxy: Self::Proxy) -> Result<Self, Error> {
+        proxy
+            .try_into()
+            .map_err(|e| Error::new_try_from_error("google.protobuf.Duration", e))
+    }
+}
+
+impl WellKnownType for SystemTime {
+    type Proxy = prost_types::Timestamp;
+
+    fn into_proxy(self) -> Result<Self::Proxy, Error> {
+        Ok(self.into())
+    }
+
+    fn from_proxy(proxy: Self::Proxy) -> Result<Self, Error> {
+        proxy
+            .try_into()
+            .map_err(|e| Error::new_try_from_error("google.protobuf.Timestamp", e))
+    }
+}
+
+/// Any time zone UTC times convert into, such as `Utc`, `FixedOffset` and, with the `clock`
+/// feature of `chrono`, `Local`. Decoded times are in UTC before they are converted.
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> WellKnownType for chrono::DateTime<Tz>
+where
+    chrono::DateTime<Tz>: From<chrono::DateTime<chrono::Utc>>,
+{
+    type Proxy = prost_types::Timestamp;
+
+    fn into_proxy(self) -> Result<Self::Proxy, Error> {
+        Ok(prost_types::Timestamp {
+            seconds: self.timestamp(),
+            nanos: self.timestamp_subsec_nanos() as i32,
+        })
+    }
+
+    fn from_proxy(mut proxy: Self::Proxy) -> Result<Self, Error> {
+        use chrono::TimeZone;
+
+        proxy.normalize();
+        chrono::Utc
+            .timestamp_opt(proxy.seconds, proxy.nanos as u32)
+            .single()
+            .map(Into::into)
+            .ok_or_else(|| {
+                Error::new_try_from_error(
+                    "google.protobuf.Timestamp",
+                    format!("timestamp out of range: {proxy}"),
+                )
+            })
+    }
+}
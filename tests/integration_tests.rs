@@ -1,6 +1,13 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::thread;
 
-use protobuf_gen::{Config, ConfigError};
+use protobuf_gen::parse;
+use protobuf_gen::print::SchemaPrinter;
+use protobuf_gen::{Config, ConfigError, Context, Diagnostic, IntegerEncoding, UnsignedEncoding};
 
 #[test]
 fn unittest_yellow_book() -> result::Result<(), ConfigError> {
@@ -13,3 +20,1633 @@ fn unittest_yellow_book() -> result::Result<(), ConfigError> {
     config.generate()?;
     Ok(())
 }
+
+#[test]
+fn unittest_incremental() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-incremental");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("book.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Book {
+            pub pages: HashMap<u32, String>,
+        }
+        "#,
+    )?;
+    fs::create_dir_all(dir.join("protos"))?;
+    fs::write(
+        dir.join("protos/shelf.proto"),
+        "syntax = \"proto3\";\npackage shelf;\nmessage Shelf {}\n",
+    )?;
+    let config = |btree_map: bool| {
+        let mut config = Config::new(dir.join("protos"), Some(dir.join("proxy")));
+        config.add_source(dir.join("book.rs"), "book");
+        config.add_import("book", "shelf.proto");
+        if btree_map {
+            config.btree_map(".");
+        }
+        config
+    };
+    config(false).generate()?;
+    let proto = dir.join("protos/book.proto");
+    let proxy = dir.join("proxy/book.rs");
+    let modified = fs::metadata(&proto)?.modified()?;
+
+    // neither the schema nor the proxy is written again if nothing changed.
+    fs::write(&proxy, "// stale")?;
+    config(false).generate()?;
+    assert_eq!(fs::metadata(&proto)?.modified()?, modified);
+    assert_eq!(fs::read_to_string(&proxy)?, "// stale");
+
+    // but a change of options runs prost again.
+    config(true).generate()?;
+    assert_ne!(fs::read_to_string(&proxy)?, "// stale");
+
+    // and so does a change of an imported schema.
+    fs::write(&proxy, "// stale")?;
+    fs::write(
+        dir.join("protos/shelf.proto"),
+        "syntax = \"proto3\";\npackage shelf;\nmessage Shelf { uint32 rows = 1; }\n",
+    )?;
+    config(true).generate()?;
+    assert_ne!(fs::read_to_string(&proxy)?, "// stale");
+
+    // schemas are renamed into place, leaving no temporary files behind.
+    fs::write(
+        dir.join("book.rs"),
+        "#[derive(ProtobufGen)] pub struct Book;",
+    )?;
+    config(true).generate()?;
+    assert!(fs::read_to_string(&proto)?.contains("reserved \"pages\";"));
+    let mut protos: Vec<_> = fs::read_dir(dir.join("protos"))?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<io::Result<_>>()?;
+    protos.sort();
+    assert_eq!(protos, ["book.proto", "shelf.proto"]);
+    Ok(())
+}
+
+#[test]
+fn unittest_borrowed_field_tags() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-borrowed-field-tags");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    let generate = |fields: &str| {
+        let source = format!(
+            "#[derive(ProtobufGen)] pub struct Note<'a> {{ {fields} \
+             #[protobuf_gen(opaque)] pub car: Option<Car> }}"
+        );
+        fs::write(dir.join("note.rs"), source)?;
+        let mut config = Config::new(dir.join("protos"), Some(dir.join("proxy")));
+        config.add_source(dir.join("note.rs"), "note");
+        config.generate()?;
+        Ok::<_, ConfigError>(fs::read_to_string(dir.join("proxy/note.rs"))?)
+    };
+
+    // only the fields written from where they are borrowed get constants.
+    let proxy = generate("pub title: &'a str, pub body: String,")?;
+    assert_eq!(proxy.matches("pub const TITLE_TAG: u32 = 1;").count(), 1);
+    assert!(!proxy.contains("BODY_TAG"));
+    assert!(!proxy.contains("CAR_TAG"));
+
+    // the constants are replaced when the borrowed fields change, but not the schema.
+    let proxy = generate("pub title: &'a str, pub body: &'a str,")?;
+    assert_eq!(proxy.matches("pub const TITLE_TAG: u32 = 1;").count(), 1);
+    assert_eq!(proxy.matches("pub const BODY_TAG: u32 = 2;").count(), 1);
+
+    let proxy = generate("pub title: String, pub body: String,")?;
+    assert!(!proxy.contains("_TAG"));
+    Ok(())
+}
+
+#[test]
+fn unittest_concurrent_writes() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-concurrent-writes");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("book.rs"),
+        "#[derive(ProtobufGen)] pub struct Book { pub title: String }",
+    )?;
+
+    // threads writing the same schemas each write their own temporary files.
+    thread::scope(|scope| {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+                    config.add_source(dir.join("book.rs"), "book");
+                    config.generate()
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .try_for_each(|thread| thread.join().unwrap())
+    })?;
+    assert!(fs::read_to_string(dir.join("protos/book.proto"))?.contains("string title = 1;"));
+    Ok(())
+}
+
+#[test]
+fn unittest_deterministic_output() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-deterministic-output");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    let sources = [
+        ("zoo.rs", "zoo", "pub struct Zebra { pub cage: Cage }"),
+        (
+            "cage.rs",
+            "zoo",
+            "pub struct Cage { pub keeper: keeper::Keeper }",
+        ),
+        (
+            "keeper.rs",
+            "keeper",
+            "pub struct Keeper { pub name: String }",
+        ),
+        ("animal.rs", "zoo", "pub enum Animal { Ant, Bee }"),
+    ];
+    for (file, _, item) in sources {
+        fs::write(dir.join(file), format!("#[derive(ProtobufGen)] {item}"))?;
+    }
+
+    let generate = |out: &str, reversed: bool| -> result::Result<Vec<String>, ConfigError> {
+        let mut config = Config::new(
+            dir.join(out).join("protos"),
+            Some(dir.join(out).join("proxy")),
+        );
+        let mut sources = sources.to_vec();
+        if reversed {
+            sources.reverse();
+        }
+        for (file, package, _) in sources {
+            config.add_source(dir.join(file), package);
+        }
+        config.generate()?;
+        [
+            "protos/zoo.proto",
+            "protos/keeper.proto",
+            "proxy/zoo.rs",
+            "proxy/keeper.rs",
+        ]
+        .into_iter()
+        .map(|path| Ok(fs::read_to_string(dir.join(out).join(path))?))
+        .collect()
+    };
+    let outputs = generate("forward", false)?;
+    assert_eq!(outputs, generate("reversed", true)?);
+    let zoo = &outputs[0];
+    assert!(zoo.find("enum Animal").unwrap() < zoo.find("message Cage").unwrap());
+    assert!(zoo.find("message Cage").unwrap() < zoo.find("message Zebra").unwrap());
+    Ok(())
+}
+
+fn render(source: &str) -> String {
+    match try_render(source) {
+        Ok(schema) => schema,
+        Err(diagnostics) => panic!("{diagnostics:?}"),
+    }
+}
+
+fn try_render(source: &str) -> result::Result<String, Vec<Diagnostic>> {
+    let file = syn::parse_str(source).unwrap();
+    let schema_file = parse::build_schema_file(&Context::default(), &file)?;
+    Ok(SchemaPrinter(&schema_file).to_string())
+}
+
+#[test]
+fn unittest_explicit_tags() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Tagged {
+            pub a: u32,
+            #[protobuf_gen(tag = 10)]
+            pub b: u32,
+            pub c: u32,
+        }
+        "#,
+    );
+    assert!(schema.contains("uint32 a = 1;"));
+    assert!(schema.contains("uint32 b = 10;"));
+    assert!(schema.contains("uint32 c = 3;"));
+
+    // positions skip the numbers taken by tags.
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Tagged {
+            #[protobuf_gen(tag = 2)]
+            pub a: u32,
+            pub b: u32,
+            pub c: u32,
+            #[protobuf_gen(tag = 3)]
+            pub d: u32,
+        }
+        "#,
+    );
+    assert!(schema.contains("uint32 a = 2;"));
+    assert!(schema.contains("uint32 b = 4;"));
+    assert!(schema.contains("uint32 c = 5;"));
+    assert!(schema.contains("uint32 d = 3;"));
+}
+
+#[test]
+#[should_panic(expected = "have the same field number 2")]
+fn unittest_duplicate_tags() {
+    render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Tagged {
+            #[protobuf_gen(tag = 2)]
+            pub a: u32,
+            pub b: u32,
+            #[protobuf_gen(tag = 2)]
+            pub c: u32,
+        }
+        "#,
+    );
+}
+
+#[test]
+fn unittest_enum_values() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub enum AreaCode {
+            Seoul,
+            Seongnam = 31,
+            Jinhae,
+            #[protobuf_gen(value = 51)]
+            Changwon,
+        }
+        "#,
+    );
+    assert!(schema.contains("Seoul = 0;"));
+    assert!(schema.contains("Seongnam = 31;"));
+    assert!(schema.contains("Jinhae = 32;"));
+    assert!(schema.contains("Changwon = 51;"));
+
+    // implicit values do not wrap around.
+    let diagnostics = try_render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub enum Edge {
+            Zero = 0,
+            Max = 2147483647,
+            Beyond,
+        }
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0]
+        .message
+        .contains("the implicit value of \"Beyond\" overflows i32"));
+}
+
+#[test]
+fn unittest_prefix_values() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(prefix_values)]
+        pub enum CarTag {
+            None,
+            CarTagNumber,
+        }
+
+        #[derive(ProtobufGen)]
+        pub enum BikeTag {
+            None,
+        }
+        "#,
+    );
+    assert!(schema.contains("CAR_TAG_NONE = 0;"));
+    assert!(schema.contains("CAR_TAG_NUMBER = 1;"));
+    assert!(schema.contains("  None = 0;"));
+}
+
+#[test]
+fn unittest_tuple_struct() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Meters(f64);
+
+        #[derive(ProtobufGen)]
+        pub struct Pair(u32, #[protobuf_gen(skip)] u32, #[protobuf_gen(name = "label")] String);
+        "#,
+    );
+    assert!(schema.contains("message Meters {\n  double field_0 = 1;\n}"));
+    assert!(schema.contains("uint32 field_0 = 1;"));
+    assert!(schema.contains("string label = 2;"));
+    assert!(!schema.contains("field_1"));
+}
+
+#[test]
+fn unittest_empty_structs() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Ping;
+
+        #[derive(ProtobufGen)]
+        pub struct Marker {}
+
+        #[derive(ProtobufGen)]
+        pub struct Hidden {
+            secret: u32,
+        }
+        "#,
+    );
+    assert!(schema.contains("message Ping {\n}"));
+    assert!(schema.contains("message Marker {\n}"));
+    assert!(schema.contains("message Hidden {\n}"));
+}
+
+#[test]
+fn unittest_tuple_variants() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub enum Command {
+            Stop,
+            Resize(u32, #[protobuf_gen(name = "height")] u32),
+            Drive(Car),
+        }
+        "#,
+    );
+    assert!(schema
+        .contains("message ResizeInner {\n    uint32 field_0 = 1;\n    uint32 height = 2;\n  }"));
+    assert!(schema.contains("ResizeInner Resize = 2;"));
+    assert!(schema.contains("Car Drive = 3;"));
+}
+
+#[test]
+fn unittest_collection_variants() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub enum Value {
+            List(Vec<u32>),
+            Maybe(Option<u32>),
+            Table(HashMap<u32, u32>),
+            Blob(Vec<u8>),
+        }
+        "#,
+    );
+    // oneof fields can't be repeated, optional or maps, so they are wrapped.
+    assert!(schema.contains("message VecOfU32 {\n  repeated uint32 items = 1;\n}"));
+    assert!(schema.contains("message OptionOfU32 {\n  optional uint32 value = 1;\n}"));
+    assert!(schema.contains("message MapOfU32ToU32 {\n  map<uint32, uint32> entries = 1;\n}"));
+    assert!(schema.contains("VecOfU32 List = 1;"));
+    assert!(schema.contains("OptionOfU32 Maybe = 2;"));
+    assert!(schema.contains("MapOfU32ToU32 Table = 3;"));
+    assert!(schema.contains("bytes Blob = 4;"));
+}
+
+#[test]
+fn unittest_recursive_source() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-recursive-source");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(dir.join("src/people/contact"))?;
+    fs::create_dir_all(dir.join("src/shared"))?;
+
+    fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+        pub mod people;
+        #[path = "shared/mod.rs"]
+        pub mod common;
+        mod inline {
+            #[derive(ProtobufGen)]
+            pub struct Inline {
+                pub id: u32,
+            }
+        }
+        #[cfg(test)]
+        mod tests;
+        "#,
+    )?;
+    fs::write(
+        dir.join("src/people.rs"),
+        r#"
+        mod contact;
+        #[derive(ProtobufGen)]
+        pub struct Person {
+            pub contact: Contact,
+            pub tag: Tag,
+        }
+        "#,
+    )?;
+    fs::write(
+        dir.join("src/people/contact/mod.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Contact {
+            pub email: String,
+        }
+        "#,
+    )?;
+    fs::write(
+        dir.join("src/shared/mod.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub enum Tag {
+            Friend,
+            Family,
+        }
+        "#,
+    )?;
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source_recursive(dir.join("src/lib.rs"), "nested");
+    config.generate()?;
+
+    let nested = fs::read_to_string(dir.join("protos/nested.proto"))?;
+    assert!(nested.contains("message Inline {"));
+    assert!(nested.contains("message Person {\n  Contact contact = 1;\n  Tag tag = 2;\n}"));
+    assert!(nested.contains("message Contact {"));
+    assert!(nested.contains("enum Tag {"));
+
+    // without the recursive mode, only the items of the file itself are converted.
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("src/lib.rs"), "nested");
+    config.generate()?;
+    let nested = fs::read_to_string(dir.join("protos/nested.proto"))?;
+    assert!(!nested.contains("message"));
+    Ok(())
+}
+
+#[test]
+fn unittest_recursive_source_missing_module() {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-recursive-source-missing-module");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.rs"), "mod missing;").unwrap();
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source_recursive(dir.join("lib.rs"), "missing");
+    assert!(matches!(config.generate(), Err(ConfigError::IoError(_))));
+}
+
+#[test]
+fn unittest_parse_error() {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-parse-error");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("broken.rs"),
+        "pub struct Broken {\n    pub a: u32,,\n}",
+    )
+    .unwrap();
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("broken.rs"), "broken");
+    let error = config.generate().unwrap_err();
+    assert!(matches!(&error, ConfigError::ParseError(path, _) if path == &dir.join("broken.rs")));
+    let message = error.to_string();
+    assert!(message.starts_with(&format!(
+        "failed to parse {}:2: ",
+        dir.join("broken.rs").display()
+    )));
+}
+
+#[test]
+fn unittest_crate_empty_prefix() {
+    let mut config = Config::new("protos", None::<PathBuf>);
+    let result = config.add_crate("src/lib.rs", "");
+    assert!(
+        matches!(result, Err(ConfigError::EmptyPackagePrefix(root)) if root == Path::new("src/lib.rs"))
+    );
+}
+
+#[test]
+fn unittest_crate() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-crate");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(dir.join("src/net"))?;
+
+    fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+        pub mod net;
+        pub mod util;
+        #[derive(ProtobufGen)]
+        pub struct Root {
+            pub peer: Peer,
+        }
+        "#,
+    )?;
+    fs::write(dir.join("src/util.rs"), "pub fn noop() {}")?;
+    fs::write(
+        dir.join("src/net/mod.rs"),
+        r#"
+        pub mod peer;
+        pub mod r#type {
+            #[derive(ProtobufGen)]
+            pub enum Kind {
+                Tcp,
+                Udp,
+            }
+        }
+        "#,
+    )?;
+    fs::write(
+        dir.join("src/net/peer.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Peer {
+            pub kind: Kind,
+        }
+        "#,
+    )?;
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_crate(dir.join("src/lib.rs"), "app")?;
+    config.generate()?;
+
+    let root = fs::read_to_string(dir.join("protos/app.proto"))?;
+    assert!(root.contains("package app;"));
+    assert!(root.contains("import \"app/net/peer.proto\";"));
+    assert!(root.contains("app.net.peer.Peer peer = 1;"));
+    let peer = fs::read_to_string(dir.join("protos/app/net/peer.proto"))?;
+    assert!(peer.contains("package app.net.peer;"));
+    assert!(peer.contains("app.net.type.Kind kind = 1;"));
+    let kind = fs::read_to_string(dir.join("protos/app/net/type.proto"))?;
+    assert!(kind.contains("enum Kind {"));
+    // modules without any types to convert have no package.
+    assert!(!dir.join("protos/app/net.proto").exists());
+    assert!(!dir.join("protos/app/util.proto").exists());
+    Ok(())
+}
+
+#[test]
+fn unittest_crate_relative_paths() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-crate-relative-paths");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(dir.join("src"))?;
+
+    fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+        pub mod a;
+        pub mod b;
+        pub mod inner {
+            #[derive(ProtobufGen)]
+            pub struct Duration {
+                pub minutes: u32,
+            }
+        }
+        #[derive(ProtobufGen)]
+        pub struct Root {
+            pub x: a::Config,
+            pub y: b::Config,
+            pub length: inner::Duration,
+        }
+        "#,
+    )?;
+    for module in ["a", "b"] {
+        fs::write(
+            dir.join(format!("src/{module}.rs")),
+            "#[derive(ProtobufGen)] pub struct Config { pub port: u32 }",
+        )?;
+    }
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_crate(dir.join("src/lib.rs"), "app")?;
+    config.generate()?;
+
+    // paths are relative to the current module before they are taken to be in other crates.
+    let root = fs::read_to_string(dir.join("protos/app.proto"))?;
+    assert!(root.contains("app.a.Config x = 1;"));
+    assert!(root.contains("app.b.Config y = 2;"));
+    assert!(root.contains("app.inner.Duration length = 3;"));
+    assert!(!root.contains("google/protobuf/duration.proto"));
+    Ok(())
+}
+
+/// Writes two packages defining a `Config` each under `dir/src`, with `user` added as a third.
+fn write_configs(dir: &Path, user: &str) -> Config {
+    fs::remove_dir_all(dir).unwrap_or_default();
+    fs::create_dir_all(dir.join("src/net")).unwrap();
+    for (path, field) in [("src/app.rs", "name"), ("src/net/mod.rs", "port")] {
+        let source = format!("#[derive(ProtobufGen)] pub struct Config {{ pub {field}: u32 }}");
+        fs::write(dir.join(path), source).unwrap();
+    }
+    fs::write(dir.join("src/user.rs"), user).unwrap();
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("src/app.rs"), "app");
+    config.add_source(dir.join("src/net/mod.rs"), "net");
+    config.add_source(dir.join("src/user.rs"), "user");
+    config
+}
+
+#[test]
+fn unittest_resolve_paths() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-resolve-paths");
+    let config = write_configs(
+        &dir,
+        r#"
+        use crate::app::Config;
+        use crate::net::{self, Config as NetConfig};
+
+        #[derive(ProtobufGen)]
+        pub struct User {
+            pub app: Config,
+            pub net: NetConfig,
+            pub qualified: crate::net::Config,
+            pub relative: super::app::Config,
+            pub through_module: net::Config,
+        }
+        "#,
+    );
+    config.generate()?;
+
+    let user = fs::read_to_string(dir.join("protos/user.proto"))?;
+    assert!(user.contains("app.Config app = 1;"));
+    assert!(user.contains("net.Config net = 2;"));
+    assert!(user.contains("net.Config qualified = 3;"));
+    assert!(user.contains("app.Config relative = 4;"));
+    assert!(user.contains("net.Config through_module = 5;"));
+    assert!(user.contains("import \"app.proto\";"));
+    assert!(user.contains("import \"net.proto\";"));
+    Ok(())
+}
+
+#[test]
+fn unittest_resolve_local_item() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-resolve-local-item");
+    let config = write_configs(
+        &dir,
+        r#"
+        use crate::net::*;
+
+        #[derive(ProtobufGen)]
+        pub struct Config {
+            pub id: u32,
+        }
+
+        #[derive(ProtobufGen)]
+        pub struct User {
+            pub config: Config,
+        }
+        "#,
+    );
+    config.generate()?;
+
+    let user = fs::read_to_string(dir.join("protos/user.proto"))?;
+    assert!(user.contains("Config config = 1;"));
+    assert!(!user.contains("import"));
+    Ok(())
+}
+
+#[test]
+fn unittest_resolve_ambiguous() {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-resolve-ambiguous");
+    let config = write_configs(
+        &dir,
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct User {
+            pub config: Config,
+        }
+        "#,
+    );
+    match config.generate() {
+        Err(ConfigError::Unsupported(diagnostics)) => {
+            assert_eq!(diagnostics.len(), 1);
+            assert!(diagnostics[0]
+                .message
+                .starts_with("ambiguous type `Config`"));
+            assert_eq!(diagnostics[0].path, dir.join("src/user.rs"));
+        }
+        result => panic!("unexpected result: {result:?}"),
+    }
+}
+
+#[test]
+fn unittest_diagnostics() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-diagnostics");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("shapes.rs"),
+        r#"#[derive(ProtobufGen)]
+pub struct Shape {
+    pub corners: (u32, u32),
+    pub name: String,
+    pub area: Box<dyn Area>,
+}
+"#,
+    )?;
+    fs::write(
+        dir.join("lines.rs"),
+        r#"#[derive(ProtobufGen)]
+pub enum Line {
+    Straight(u32),
+    Curved(&'static u32),
+}
+"#,
+    )?;
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("shapes.rs"), "shapes");
+    config.add_source(dir.join("lines.rs"), "lines");
+    let mut diagnostics = match config.generate() {
+        Err(ConfigError::Unsupported(diagnostics)) => diagnostics,
+        result => panic!("unexpected result: {result:?}"),
+    };
+    diagnostics.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+
+    // every error of every package is reported.
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].path, dir.join("lines.rs"));
+    assert_eq!((diagnostics[0].line, diagnostics[0].column), (4, 12));
+    assert_eq!(diagnostics[0].item, "Line");
+    assert_eq!(diagnostics[0].field.as_deref(), Some("Curved"));
+    assert_eq!(diagnostics[1].path, dir.join("shapes.rs"));
+    assert_eq!((diagnostics[1].line, diagnostics[1].column), (3, 18));
+    assert_eq!(diagnostics[1].item, "Shape");
+    assert_eq!(diagnostics[1].field.as_deref(), Some("corners"));
+    assert!(diagnostics[1]
+        .message
+        .contains("tuples cannot be converted"));
+    assert_eq!(diagnostics[2].field.as_deref(), Some("area"));
+    assert_eq!(
+        diagnostics[2].to_string(),
+        format!(
+            "{}:5:19: unsupported type `dyn Area`: trait objects cannot be converted (in \
+             `Shape::area`)",
+            dir.join("shapes.rs").display()
+        )
+    );
+    // nothing is generated from sources with errors.
+    assert!(!dir.join("protos/shapes.proto").exists());
+    Ok(())
+}
+
+#[test]
+fn unittest_generic() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-generic");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+
+    fs::write(
+        dir.join("paging.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(instantiate(PageOfPerson = "Page<Person>", PageOfTag = "Page<Tag>"))]
+        pub struct Page<T> {
+            pub items: Vec<T>,
+            pub next: Option<u32>,
+        }
+        "#,
+    )?;
+    fs::write(
+        dir.join("directory.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Directory {
+            pub people: Page<Person>,
+        }
+        "#,
+    )?;
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("paging.rs"), "paging");
+    config.add_source(dir.join("directory.rs"), "directory");
+    config.generate()?;
+
+    let paging = fs::read_to_string(dir.join("protos/paging.proto"))?;
+    assert!(paging.contains("message PageOfPerson {\n  repeated Person items = 1;"));
+    assert!(paging.contains("message PageOfTag {\n  repeated Tag items = 1;"));
+    assert!(!paging.contains("message Page {"));
+    let directory = fs::read_to_string(dir.join("protos/directory.proto"))?;
+    assert!(directory.contains("import \"paging.proto\";"));
+    assert!(directory.contains("paging.PageOfPerson people = 1;"));
+    Ok(())
+}
+
+#[test]
+fn unittest_generic_instance_paths() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-generic-instance-paths");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(dir.join("src"))?;
+
+    fs::write(
+        dir.join("src/lib.rs"),
+        r#"
+        pub mod paging;
+        pub mod people;
+        #[derive(ProtobufGen)]
+        pub struct Directory {
+            pub a: paging::Page<people::Person>,
+            pub b: crate::paging::Page<crate::people::Person>,
+            pub c: paging::Page<u32>,
+        }
+        "#,
+    )?;
+    fs::write(
+        dir.join("src/paging.rs"),
+        r#"
+        use crate::people::Person;
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(instantiate(PageOfPerson = "Page<Person>", PageOfU32 = "Page<u32>"))]
+        pub struct Page<T> {
+            pub items: Vec<T>,
+        }
+        "#,
+    )?;
+    fs::write(
+        dir.join("src/people.rs"),
+        "#[derive(ProtobufGen)] pub struct Person { pub name: String }",
+    )?;
+
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_crate(dir.join("src/lib.rs"), "app")?;
+    config.generate()?;
+
+    // type arguments are matched by the items they resolve to, however they are written.
+    let root = fs::read_to_string(dir.join("protos/app.proto"))?;
+    assert!(root.contains("app.paging.PageOfPerson a = 1;"));
+    assert!(root.contains("app.paging.PageOfPerson b = 2;"));
+    assert!(root.contains("app.paging.PageOfU32 c = 3;"));
+    Ok(())
+}
+
+#[test]
+fn unittest_generic_missing_instance() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-generic-missing-instance");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+
+    fs::write(
+        dir.join("paging.rs"),
+        r#"#[derive(ProtobufGen)]
+#[protobuf_gen(instantiate(PageOfPerson = "Page<Person>"))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+}
+
+#[derive(ProtobufGen)]
+pub struct Directory {
+    pub tags: Page<Tag>,
+}
+"#,
+    )?;
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("paging.rs"), "paging");
+    let diagnostics = match config.generate() {
+        Err(ConfigError::Unsupported(diagnostics)) => diagnostics,
+        result => panic!("unexpected result: {result:?}"),
+    };
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        (diagnostics[0].line, diagnostics[0].item.as_str()),
+        (9, "Directory")
+    );
+    assert!(diagnostics[0]
+        .message
+        .contains("no instance of `Page` matches it"));
+    Ok(())
+}
+
+#[test]
+fn unittest_generic_diagnostics() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-generic-diagnostics");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+
+    fs::write(
+        dir.join("catalog.rs"),
+        r#"#[derive(ProtobufGen)]
+pub struct Catalog {
+    pub name: String,
+}
+"#,
+    )?;
+    fs::write(
+        dir.join("paging.rs"),
+        r#"#[derive(ProtobufGen)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+}
+
+#[derive(ProtobufGen)]
+#[protobuf_gen(instantiate(PageOfTag = "Book<Tag>"))]
+pub struct Chapter<T> {
+    pub items: Vec<T>,
+}
+
+#[derive(ProtobufGen)]
+pub struct Pair(#[protobuf_gen(name = "1st")] pub u32, pub u32);
+"#,
+    )?;
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("catalog.rs"), "catalog");
+    config.add_source(dir.join("paging.rs"), "paging");
+    let mut diagnostics = match config.generate() {
+        Err(ConfigError::Unsupported(diagnostics)) => diagnostics,
+        result => panic!("unexpected result: {result:?}"),
+    };
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(
+        (diagnostics[0].line, diagnostics[0].item.as_str()),
+        (2, "Page")
+    );
+    assert!(diagnostics[0]
+        .message
+        .contains("generic `Page` needs instances"));
+    assert_eq!(
+        (diagnostics[1].line, diagnostics[1].item.as_str()),
+        (7, "Chapter")
+    );
+    assert!(diagnostics[1]
+        .message
+        .contains("invalid instance of `Chapter`: `Book < Tag >`"));
+    assert_eq!(
+        (diagnostics[2].line, diagnostics[2].item.as_str()),
+        (13, "Pair")
+    );
+    assert!(diagnostics[2]
+        .message
+        .contains("invalid field name: \"1st\""));
+    // the packages without errors are not written either.
+    assert!(!dir.join("protos").exists());
+    Ok(())
+}
+
+#[test]
+fn unittest_malformed_instances() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-malformed-instances");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+
+    fs::write(
+        dir.join("paging.rs"),
+        r#"#[derive(ProtobufGen)]
+#[protobuf_gen(instantiate(PageOfTag = "Page<Tag"))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+}
+
+#[derive(ProtobufGen)]
+#[protobuf_gen(instantiate("Chapter<Tag>"))]
+pub struct Chapter<T> {
+    pub items: Vec<T>,
+}
+"#,
+    )?;
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("paging.rs"), "paging");
+    let diagnostics = match config.generate() {
+        Err(ConfigError::Unsupported(diagnostics)) => diagnostics,
+        result => panic!("unexpected result: {result:?}"),
+    };
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].path, dir.join("paging.rs"));
+    assert_eq!(
+        (diagnostics[0].line, diagnostics[0].item.as_str()),
+        (2, "Page")
+    );
+    assert!(diagnostics[0].message.contains("invalid instance type"));
+    assert_eq!(
+        (diagnostics[1].line, diagnostics[1].item.as_str()),
+        (8, "Chapter")
+    );
+    assert!(diagnostics[1]
+        .message
+        .contains("instances must be named types"));
+    Ok(())
+}
+
+#[test]
+fn unittest_well_known_types() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Schedule {
+            pub interval: std::time::Duration,
+            pub started: SystemTime,
+            pub laps: Vec<Duration>,
+        }
+        "#,
+    );
+    assert!(schema.contains("import \"google/protobuf/duration.proto\";"));
+    assert!(schema.contains("import \"google/protobuf/timestamp.proto\";"));
+    assert!(schema.contains("google.protobuf.Duration interval = 1;"));
+    assert!(schema.contains("google.protobuf.Timestamp started = 2;"));
+    assert!(schema.contains("repeated google.protobuf.Duration laps = 3;"));
+}
+
+#[test]
+fn unittest_well_known_names() -> result::Result<(), ConfigError> {
+    // items named like well-known types are what the name refers to unless it is imported.
+    let dir = env::temp_dir().join("protobuf-gen-unittest-well-known-names");
+    let config = write_configs(
+        &dir,
+        r#"
+        use std::time;
+
+        #[derive(ProtobufGen)]
+        pub struct Duration {
+            pub minutes: u32,
+        }
+
+        #[derive(ProtobufGen)]
+        pub struct Plan {
+            pub length: Duration,
+            pub timeout: time::Duration,
+            pub started: ::std::time::SystemTime,
+        }
+        "#,
+    );
+    config.generate()?;
+    let schema = fs::read_to_string(dir.join("protos/user.proto"))?;
+    assert!(schema.contains("  Duration length = 1;"));
+    assert!(schema.contains("google.protobuf.Duration timeout = 2;"));
+    assert!(schema.contains("google.protobuf.Timestamp started = 3;"));
+
+    let diagnostics = try_render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Plan {
+            pub length: chrono::Duration,
+        }
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0]
+        .message
+        .contains("`chrono::Duration` is not a well-known type"));
+    Ok(())
+}
+
+#[test]
+fn unittest_bytes_names() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-bytes-names");
+    let mut config = write_configs(
+        &dir,
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Bytes {
+            pub size: u32,
+        }
+
+        #[derive(ProtobufGen)]
+        pub struct Upload {
+            pub size: Bytes,
+            pub sizes: Vec<Bytes>,
+        }
+        "#,
+    );
+    fs::write(
+        dir.join("src/chunk.rs"),
+        r#"
+        use bytes::Bytes;
+
+        #[derive(ProtobufGen)]
+        pub struct Chunk {
+            pub data: Bytes,
+        }
+        "#,
+    )?;
+    config.add_source(dir.join("src/chunk.rs"), "chunk");
+    config.generate()?;
+
+    let user = fs::read_to_string(dir.join("protos/user.proto"))?;
+    assert!(user.contains("  Bytes size = 1;"));
+    assert!(user.contains("repeated Bytes sizes = 2;"));
+    let chunk = fs::read_to_string(dir.join("protos/chunk.proto"))?;
+    assert!(chunk.contains("bytes data = 1;"));
+    assert!(!chunk.contains("import"));
+    Ok(())
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn unittest_date_time() {
+    let schema = render(
+        r#"
+        use chrono::{DateTime, FixedOffset};
+
+        #[derive(ProtobufGen)]
+        pub struct Meeting {
+            pub starts: DateTime<chrono::Utc>,
+            pub ends: chrono::DateTime<FixedOffset>,
+        }
+        "#,
+    );
+    assert!(schema.contains("google.protobuf.Timestamp starts = 1;"));
+    assert!(schema.contains("google.protobuf.Timestamp ends = 2;"));
+
+    let diagnostics = try_render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Meeting {
+            pub starts: chrono::DateTime<chrono_tz::Tz>,
+        }
+        "#,
+    )
+    .unwrap_err();
+    assert!(diagnostics[0]
+        .message
+        .contains("time zone `Tz` cannot be converted"));
+}
+
+#[test]
+fn unittest_integer_encoding() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Checksum {
+            #[protobuf_gen(encoding = "fixed64")]
+            pub hash: u64,
+            pub offset: i32,
+            pub length: i16,
+            #[protobuf_gen(encoding = "sfixed32")]
+            pub deltas: Vec<i32>,
+            #[protobuf_gen(encoding = "fixed32")]
+            pub sizes: HashMap<String, u32>,
+        }
+        "#,
+    );
+    assert!(schema.contains("fixed64 hash = 1;"));
+    assert!(schema.contains("sint32 offset = 2;"));
+    assert!(schema.contains("int32 length = 3;"));
+    assert!(schema.contains("repeated sfixed32 deltas = 4;"));
+    assert!(schema.contains("map<string, fixed32> sizes = 5;"));
+}
+
+#[test]
+fn unittest_integer_encoding_of_string() {
+    let diagnostics = try_render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Named {
+            #[protobuf_gen(encoding = "fixed64")]
+            pub name: String,
+        }
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].field.as_deref(), Some("name"));
+    assert_eq!(
+        diagnostics[0].message,
+        "encoding \"fixed64\" only applies to integers"
+    );
+}
+
+#[test]
+fn unittest_default_integer_encoding() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-default-integer-encoding");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+
+    fs::write(
+        dir.join("encoded.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Encoded {
+            pub a: i32,
+            pub b: i64,
+            pub c: u16,
+            pub d: u64,
+            #[protobuf_gen(encoding = "uint64")]
+            pub e: u64,
+        }
+        "#,
+    )?;
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("encoded.rs"), "encoded");
+    config.signed_encoding(IntegerEncoding::Varint);
+    config.unsigned_encoding(UnsignedEncoding::Fixed);
+    config.generate()?;
+
+    let schema = fs::read_to_string(dir.join("protos/encoded.proto"))?;
+    assert!(schema.contains(" int32 a = 1;"));
+    assert!(schema.contains(" int64 b = 2;"));
+    assert!(schema.contains(" fixed32 c = 3;"));
+    assert!(schema.contains(" fixed64 d = 4;"));
+    assert!(schema.contains(" uint64 e = 5;"));
+    Ok(())
+}
+
+#[test]
+fn unittest_map() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Maps {
+            pub hash_map: HashMap<String, Person>,
+            pub btree_map: BTreeMap<u32, String>,
+            pub index_map: indexmap::IndexMap<i64, f64>,
+        }
+        "#,
+    );
+    assert!(schema.contains("map<string, Person> hash_map = 1;"));
+    assert!(schema.contains("map<uint32, string> btree_map = 2;"));
+    assert!(schema.contains("map<sint64, double> index_map = 3;"));
+}
+
+#[test]
+fn unittest_collections() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Collections {
+            pub a: BTreeSet<String>,
+            pub b: VecDeque<u32>,
+            pub c: LinkedList<Tag>,
+            pub d: BinaryHeap<u64>,
+            pub e: SmallVec<[Tag; 4]>,
+            pub f: [f32; 3],
+            pub g: std::collections::BTreeMap<String, Tag>,
+            pub h: Option<Tag>,
+        }
+        "#,
+    );
+    assert!(schema.contains("repeated string a = 1;"));
+    assert!(schema.contains("repeated uint32 b = 2;"));
+    assert!(schema.contains("repeated Tag c = 3;"));
+    assert!(schema.contains("repeated uint64 d = 4;"));
+    assert!(schema.contains("repeated Tag e = 5;"));
+    assert!(schema.contains("repeated float f = 6;"));
+    assert!(schema.contains("map<string, Tag> g = 7;"));
+    assert!(schema.contains("optional Tag h = 8;"));
+}
+
+#[test]
+fn unittest_bytes() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Blobs {
+            pub a: Vec<u8>,
+            pub b: [u8; 32],
+            pub c: bytes::Bytes,
+            pub d: Option<Vec<u8>>,
+            pub e: Vec<[u8; 16]>,
+            pub f: HashMap<String, Vec<u8>>,
+            pub g: VecDeque<u8>,
+        }
+        "#,
+    );
+    assert!(schema.contains("bytes a = 1;"));
+    assert!(schema.contains("bytes b = 2;"));
+    assert!(schema.contains("bytes c = 3;"));
+    assert!(schema.contains("optional bytes d = 4;"));
+    assert!(schema.contains("repeated bytes e = 5;"));
+    assert!(schema.contains("map<string, bytes> f = 6;"));
+    assert!(schema.contains("repeated uint32 g = 7;"));
+}
+
+#[test]
+fn unittest_borrowed() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Borrowed<'a> {
+            pub a: &'a str,
+            pub b: &'a [u8],
+            pub c: Cow<'a, str>,
+            pub d: std::borrow::Cow<'a, [u8]>,
+            pub e: Option<&'a str>,
+            pub f: Vec<Cow<'a, str>>,
+        }
+        "#,
+    );
+    assert!(schema.contains("string a = 1;"));
+    assert!(schema.contains("bytes b = 2;"));
+    assert!(schema.contains("string c = 3;"));
+    assert!(schema.contains("bytes d = 4;"));
+    assert!(schema.contains("optional string e = 5;"));
+    assert!(schema.contains("repeated string f = 6;"));
+}
+
+#[test]
+#[should_panic(expected = "can be borrowed")]
+fn unittest_borrowed_unsupported() {
+    render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Borrowed<'a> {
+            pub a: &'a u32,
+        }
+        "#,
+    );
+}
+
+#[test]
+fn unittest_scalars() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Scalars {
+            pub a: bool,
+            pub b: char,
+            pub c: u128,
+            pub d: i128,
+            pub e: NonZeroU8,
+            pub f: std::num::NonZeroI64,
+            pub g: NonZero<u32>,
+            pub h: HashMap<bool, NonZeroU64>,
+        }
+        "#,
+    );
+    assert!(schema.contains("bool a = 1;"));
+    assert!(schema.contains("uint32 b = 2;"));
+    assert!(schema.contains("bytes c = 3;"));
+    assert!(schema.contains("bytes d = 4;"));
+    assert!(schema.contains("uint32 e = 5;"));
+    assert!(schema.contains("sint64 f = 6;"));
+    assert!(schema.contains("uint32 g = 7;"));
+    assert!(schema.contains("map<bool, uint64> h = 8;"));
+}
+
+#[test]
+fn unittest_nested_collections() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Nested {
+            pub a: Option<Vec<Person>>,
+            pub b: Vec<Vec<Person>>,
+            pub c: Vec<Option<u32>>,
+            pub d: HashMap<String, Vec<Person>>,
+            pub e: Vec<BTreeMap<u32, Vec<u8>>>,
+        }
+        "#,
+    );
+    assert!(schema.contains("message VecOfPerson {\n  repeated Person items = 1;\n}"));
+    assert!(schema.contains("message OptionOfU32 {\n  optional uint32 value = 1;\n}"));
+    assert!(schema.contains("message MapOfU32ToBytes {\n  map<uint32, bytes> entries = 1;\n}"));
+    assert_eq!(schema.matches("message VecOfPerson").count(), 1);
+    assert!(schema.contains("optional VecOfPerson a = 1;"));
+    assert!(schema.contains("repeated VecOfPerson b = 2;"));
+    assert!(schema.contains("repeated OptionOfU32 c = 3;"));
+    assert!(schema.contains("map<string, VecOfPerson> d = 4;"));
+    assert!(schema.contains("repeated MapOfU32ToBytes e = 5;"));
+}
+
+#[test]
+#[should_panic(expected = "wrapper message `VecOfU32` for `Vec < u32 >` conflicts")]
+fn unittest_wrapper_conflict() {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-wrapper-conflict");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(
+        dir.join("matrix.rs"),
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct VecOfU32 {
+            pub items: Vec<u32>,
+        }
+
+        #[derive(ProtobufGen)]
+        pub struct Matrix {
+            pub rows: Vec<Vec<u32>>,
+        }
+        "#,
+    )
+    .unwrap();
+    let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+    config.add_source(dir.join("matrix.rs"), "matrix");
+    config.generate().unwrap();
+}
+
+#[test]
+fn unittest_pointers() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Node {
+            pub value: Box<u32>,
+            pub left: Box<Node>,
+            pub right: Option<std::rc::Rc<Node>>,
+            pub children: Vec<Arc<Node>>,
+        }
+        "#,
+    );
+    assert!(schema.contains("uint32 value = 1;"));
+    assert!(schema.contains("Node left = 2;"));
+    assert!(schema.contains("optional Node right = 3;"));
+    assert!(schema.contains("repeated Node children = 4;"));
+}
+
+#[test]
+#[should_panic(expected = "pointers to collections cannot be converted")]
+fn unittest_pointer_to_collection() {
+    render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Boxed {
+            pub items: Box<Vec<u32>>,
+        }
+        "#,
+    );
+}
+
+#[test]
+#[should_panic(expected = "invalid map key type `f32`")]
+fn unittest_invalid_map_key() {
+    render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct FloatKeys {
+            pub map: HashMap<f32, String>,
+        }
+        "#,
+    );
+}
+
+#[test]
+fn unittest_reserved() {
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(reserved(2, 5, 6, 7, "old_name"))]
+        pub struct Reserved {
+            pub a: u32,
+            pub b: u32,
+        }
+
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(reserved(5, "Old"))]
+        pub enum ReservedEnum {
+            Zero,
+            One,
+        }
+        "#,
+    );
+    assert!(schema.contains("uint32 a = 1;"));
+    assert!(schema.contains("uint32 b = 3;"));
+    assert!(schema.contains("reserved 2, 5 to 7;"));
+    assert!(schema.contains("reserved \"old_name\";"));
+    assert!(schema.contains("reserved 5;"));
+    assert!(schema.contains("reserved \"Old\";"));
+}
+
+#[test]
+fn unittest_reserved_fresh_items() {
+    // reserved numbers are skipped even before the lock file knows the items.
+    let schema = render(
+        r#"
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(reserved(1, 3))]
+        pub struct Fresh {
+            pub a: u32,
+            pub b: u32,
+        }
+
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(reserved(1))]
+        pub enum FreshEnum {
+            A,
+            B,
+        }
+
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(reserved(1))]
+        pub enum FreshOneOf {
+            A(u32),
+            B(u32),
+        }
+        "#,
+    );
+    assert!(schema.contains("uint32 a = 2;"));
+    assert!(schema.contains("uint32 b = 4;"));
+    assert!(schema.contains("A = 0;"));
+    assert!(schema.contains("B = 2;"));
+    assert!(schema.contains("uint32 A = 2;"));
+    assert!(schema.contains("uint32 B = 3;"));
+}
+
+#[test]
+fn unittest_lock_file() -> result::Result<(), ConfigError> {
+    let dir = env::temp_dir().join("protobuf-gen-unittest-lock-file");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+
+    let generate = |source: &str| -> result::Result<String, ConfigError> {
+        fs::write(dir.join("locked.rs"), source)?;
+        let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+        config.add_source(dir.join("locked.rs"), "locked");
+        config.generate()?;
+        Ok(fs::read_to_string(dir.join("protos/locked.proto"))?)
+    };
+
+    generate(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Locked {
+            pub a: u32,
+            pub b: u32,
+            pub c: u32,
+        }
+        "#,
+    )?;
+    let schema = generate(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Locked {
+            pub d: u32,
+            pub a: u32,
+            pub c: u32,
+        }
+        "#,
+    )?;
+    assert!(schema.contains("uint32 d = 4;"));
+    assert!(schema.contains("uint32 a = 1;"));
+    assert!(schema.contains("uint32 c = 3;"));
+    assert!(schema.contains("reserved 2;"));
+    assert!(schema.contains("reserved \"b\";"));
+    assert!(fs::read_to_string(dir.join("protos.lock"))?.contains("locked.Locked.b = 2"));
+
+    // explicit tags cannot take the number of a removed field.
+    let result = generate(
+        r#"
+        #[derive(ProtobufGen)]
+        pub struct Locked {
+            #[protobuf_gen(tag = 2)]
+            pub e: u32,
+            pub a: u32,
+            pub c: u32,
+        }
+        "#,
+    );
+    match result {
+        Err(ConfigError::Unsupported(diagnostics)) => {
+            assert_eq!(diagnostics.len(), 1);
+            assert!(diagnostics[0]
+                .message
+                .contains("\"e\": tag 2 was used by the removed field \"b\""));
+        }
+        result => panic!("unexpected result: {result:?}"),
+    }
+    assert!(fs::read_to_string(dir.join("protos.lock"))?.contains("locked.Locked.b = 2"));
+    Ok(())
+}
+
+#[test]
+fn unittest_enum_without_zero() -> result::Result<(), ConfigError> {
+    let diagnostics = try_render(
+        r#"
+        #[derive(ProtobufGen)]
+        pub enum Level {
+            Low = 1,
+            High = 2,
+        }
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0].message.contains("to have the value 0"));
+
+    // reserving 0 leaves a new enum without a zero value.
+    let diagnostics = try_render(
+        r#"
+        #[derive(ProtobufGen)]
+        #[protobuf_gen(reserved(0))]
+        pub enum Level {
+            Low,
+            High,
+        }
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0].message.contains("but 0 is reserved"));
+
+    // removing the variant locked at 0 leaves the enum without a zero value too.
+    let dir = env::temp_dir().join("protobuf-gen-unittest-enum-without-zero");
+    fs::remove_dir_all(&dir).unwrap_or_default();
+    fs::create_dir_all(&dir)?;
+    let generate = |source: &str| {
+        fs::write(dir.join("light.rs"), source)?;
+        let mut config = Config::new(dir.join("protos"), None::<PathBuf>);
+        config.add_source(dir.join("light.rs"), "light");
+        config.generate()
+    };
+    generate("#[derive(ProtobufGen)] pub enum Light { Off, On }")?;
+    match generate("#[derive(ProtobufGen)] pub enum Light { On, Dimmed }") {
+        Err(ConfigError::Unsupported(diagnostics)) => {
+            assert_eq!(diagnostics[0].item, "Light");
+            assert!(diagnostics[0].message.contains("locked at 0 was removed"));
+        }
+        result => panic!("unexpected result: {result:?}"),
+    }
+    Ok(())
+}